@@ -1,7 +1,8 @@
 use core::mem;
+use cranelift_jit_demo::error::JitError;
 use cranelift_jit_demo::jit;
 
-fn main() -> Result<(), String> {
+fn main() -> Result<(), JitError> {
     // Create the JIT instance, which manages all generated functions and data.
     let mut jit = jit::JIT::default();
     println!("the answer is: {}", run_foo(&mut jit)?);
@@ -13,24 +14,102 @@ fn main() -> Result<(), String> {
         "iterative_fib(10) = {}",
         run_iterative_fib_code(&mut jit, 10)?
     );
+    println!("is_even(10) = {}", run_is_even(&mut jit, 10)?);
+    println!("digit_sum(1234) = {}", run_digit_sum(&mut jit, 1234)?);
+    println!("sqrt(2) = {}", run_sqrt(&mut jit, 2.0)?);
+    println!("parse_float() = {}", run_parse_float(&mut jit)?);
+    println!("sum_numbers() = {}", run_sum_numbers(&mut jit)?);
+    println!("median_number() = {}", run_median_number(&mut jit)?);
+    println!("manhattan_length() = {}", run_manhattan_length(&mut jit)?);
+    run_next_ticket(&mut jit)?;
+    println!("stack_machine() = {}", run_stack_machine(&mut jit)?);
     run_hello(&mut jit)?;
     Ok(())
 }
 
-fn run_foo(jit: &mut jit::JIT) -> Result<isize, String> {
+fn run_foo(jit: &mut jit::JIT) -> Result<isize, JitError> {
     unsafe { run_code(jit, FOO_CODE, (1, 0)) }
 }
 
-fn run_recursive_fib_code(jit: &mut jit::JIT, input: isize) -> Result<isize, String> {
+fn run_recursive_fib_code(jit: &mut jit::JIT, input: isize) -> Result<isize, JitError> {
     unsafe { run_code(jit, RECURSIVE_FIB_CODE, input) }
 }
 
-fn run_iterative_fib_code(jit: &mut jit::JIT, input: isize) -> Result<isize, String> {
+fn run_iterative_fib_code(jit: &mut jit::JIT, input: isize) -> Result<isize, JitError> {
     unsafe { run_code(jit, ITERATIVE_FIB_CODE, input) }
 }
 
-fn run_hello(jit: &mut jit::JIT) -> Result<isize, String> {
-    jit.create_data("hello_string", "hello world!\0".as_bytes().to_vec())?;
+fn run_is_even(jit: &mut jit::JIT, input: isize) -> Result<isize, JitError> {
+    // Both functions need to be compiled together, since each calls the other.
+    let code = jit.compile_program(EVEN_ODD_CODE)?;
+    let code_fn = unsafe { mem::transmute::<*const u8, fn(isize) -> isize>(code["is_even"]) };
+    Ok(code_fn(input))
+}
+
+fn run_digit_sum(jit: &mut jit::JIT, input: i64) -> Result<i64, JitError> {
+    jit.compile_program(DIVMOD_CODE)?;
+    // `call` checks the signature, and it can return several values at once.
+    // It's still unsafe, since the JIT'd code can access any memory.
+    let (q, r): (i64, i64) = unsafe { jit.call("divmod", (input, 10_i64))? };
+    println!("divmod({input}, 10) = ({q}, {r})");
+    let (sum,) = unsafe { jit.call("digit_sum", (input,))? };
+    Ok(sum)
+}
+
+fn run_sqrt(jit: &mut jit::JIT, input: f64) -> Result<f64, JitError> {
+    jit.compile(SQRT_CODE)?;
+    let (root,) = unsafe { jit.call("sqrt", (input,))? };
+    Ok(root)
+}
+
+fn run_parse_float(jit: &mut jit::JIT) -> Result<f64, JitError> {
+    jit.compile_program(PARSE_FLOAT_CODE)?;
+    let (value,) = unsafe { jit.call("parse_float", ())? };
+    Ok(value)
+}
+
+fn run_sum_numbers(jit: &mut jit::JIT) -> Result<i64, JitError> {
+    let numbers = [3_i32, 1, 4, 1, 5, 9, 2, 6];
+    let bytes = numbers.iter().flat_map(|n| n.to_le_bytes()).collect();
+    jit.create_data("numbers", bytes)?;
+    jit.compile(SUM_NUMBERS_CODE)?;
+    let (sum,) = unsafe { jit.call("sum_numbers", (numbers.len() as i64,))? };
+    Ok(sum)
+}
+
+fn run_median_number(jit: &mut jit::JIT) -> Result<i64, JitError> {
+    // This uses the `numbers` data object created by `run_sum_numbers`.
+    jit.compile(MEDIAN_NUMBER_CODE)?;
+    let (median,) = unsafe { jit.call("median_number", ())? };
+    Ok(median)
+}
+
+fn run_manhattan_length(jit: &mut jit::JIT) -> Result<i64, JitError> {
+    // The struct has to be declared before data can be created from a
+    // literal of it, and the data has to exist before code can refer to it.
+    jit.compile_program(POINT_CODE)?;
+    jit.create_struct_data("offset", "Point { x: 3, y: -4 }")?;
+    jit.compile(MANHATTAN_LENGTH_CODE)?;
+    let (length,) = unsafe { jit.call("manhattan_length", ())? };
+    Ok(length)
+}
+
+fn run_next_ticket(jit: &mut jit::JIT) -> Result<(), JitError> {
+    jit.compile_program(NEXT_TICKET_CODE)?;
+    for _ in 0..2 {
+        let (ticket,): (i64,) = unsafe { jit.call("next_ticket", ())? };
+        println!("next_ticket() = {ticket}");
+    }
+    Ok(())
+}
+
+fn run_stack_machine(jit: &mut jit::JIT) -> Result<i64, JitError> {
+    jit.compile_program(STACK_MACHINE_CODE)?;
+    let (result,) = unsafe { jit.call("stack_machine", ())? };
+    Ok(result)
+}
+
+fn run_hello(jit: &mut jit::JIT) -> Result<isize, JitError> {
     unsafe { run_code(jit, HELLO_CODE, ()) }
 }
 
@@ -42,15 +121,23 @@ fn run_hello(jit: &mut jit::JIT) -> Result<isize, String> {
 ///
 /// This function is unsafe since it relies on the caller to provide it with the correct
 /// input and output types. Using incorrect types at this point may corrupt the program's state.
-unsafe fn run_code<I, O>(jit: &mut jit::JIT, code: &str, input: I) -> Result<O, String> { unsafe {
+unsafe fn run_code<I, O>(jit: &mut jit::JIT, code: &str, input: I) -> Result<O, JitError> { unsafe {
     // Pass the string to the JIT, and it returns a raw pointer to machine code.
-    let code_ptr = jit.compile(code)?;
+    // If the code is malformed, show where the problem is before bailing out.
+    let code_ptr = jit
+        .compile(code)
+        .inspect_err(|err| eprint!("{}", err.render(code)))?;
     // Cast the raw pointer to a typed function pointer. This is unsafe, because
     // this is the critical point where you have to trust that the generated code
     // is safe to be called.
-    let code_fn = mem::transmute::<_, fn(I) -> O>(code_ptr);
+    let code_fn = mem::transmute::<*const u8, fn(I) -> O>(code_ptr);
     // And now we can call it!
-    Ok(code_fn(input))
+    let output = code_fn(input);
+    // If the code hit a runtime error, report that instead of its output.
+    match jit.take_trap() {
+        Some(trap) => Err(JitError::Trap(trap)),
+        None => Ok(output),
+    }
 }}
 
 // A small test function.
@@ -78,14 +165,12 @@ const FOO_CODE: &str = r#"
 const RECURSIVE_FIB_CODE: &str = r#"
     fn recursive_fib(n) -> (r) {
         r = if n == 0 {
-                    0
-            } else {
-                if n == 1 {
-                    1
-                } else {
-                    recursive_fib(n - 1) + recursive_fib(n - 2)
-                }
-            }
+            0
+        } else if n == 1 {
+            1
+        } else {
+            recursive_fib(n - 1) + recursive_fib(n - 2)
+        }
     }
 "#;
 
@@ -108,10 +193,174 @@ const ITERATIVE_FIB_CODE: &str = r#"
     }
 "#;
 
+/// Several functions can be compiled together as one program, which lets
+/// them call each other regardless of the order they're written in.
+const EVEN_ODD_CODE: &str = r#"
+    fn is_even(n) -> (r) {
+        r = if n == 0 {
+            1
+        } else {
+            is_odd(n - 1)
+        }
+    }
+
+    fn is_odd(n) -> (r) {
+        r = if n == 0 {
+            0
+        } else {
+            is_even(n - 1)
+        }
+    }
+"#;
+
+/// A function can return several values, which the caller can destructure
+/// into variables.
+const DIVMOD_CODE: &str = r#"
+    fn divmod(a, b) -> (q, r) {
+        q = a / b
+        r = a % b
+    }
+
+    fn digit_sum(n) -> (s) {
+        while n != 0 {
+            (n, d) = divmod(n, 10)
+            s = s + d
+        }
+    }
+"#;
+
+/// Values have type `i64` unless they're annotated with another type, such
+/// as `f64`. This finds a square root with Newton's method.
+const SQRT_CODE: &str = r#"
+    fn sqrt(x: f64) -> (r: f64) {
+        r = if x > 1.0 { x / 2.0 } else { 1.0 }
+        for i in 0..20 {
+            r = (r + x / r) * 0.5
+        }
+    }
+"#;
+
+/// Functions provided by the host, such as `atof` from the C library, are
+/// declared with `extern fn`, which gives the types of their parameters and
+/// return values. Without a declaration, a host function is assumed to
+/// return an `i64`.
+const PARSE_FLOAT_CODE: &str = r#"
+    extern fn atof(s: ptr) -> (r: f64)
+
+    fn parse_float() -> (r: f64) {
+        r = atof("2.5") * 2.0
+    }
+"#;
+
+/// Memory is read with `load8` through `load64`, and written with `store8`
+/// through `store64`, named after the width of the value. `&numbers` is the
+/// address of the data object of that name, which is defined above.
+const SUM_NUMBERS_CODE: &str = r#"
+    fn sum_numbers(count) -> (sum) {
+        for i in 0..count {
+            sum = sum + load32(&numbers + i * 4) as i64
+        }
+    }
+"#;
+
+/// A function can declare local arrays with `let`, which live on the stack.
+/// This copies the numbers into one, sorts them, and picks the middle one.
+const MEDIAN_NUMBER_CODE: &str = r#"
+    fn median_number() -> (r) {
+        let sorted[8]: i32
+        for i in 0..8 {
+            x = load32(&numbers + i * 4)
+            j = i
+            while j > 0 && sorted[j - 1] > x {
+                sorted[j] = sorted[j - 1]
+                j = j - 1
+            }
+            sorted[j] = x
+        }
+        r = sorted[4] as i64
+    }
+"#;
+
+/// Structs can be declared alongside functions. Their fields are laid out
+/// the same way as C would lay them out.
+const POINT_CODE: &str = r#"
+    struct Point {
+        x: i64,
+        y: i64,
+    }
+"#;
+
+/// A `&Point` is a pointer to a `Point`, whose fields can be accessed with
+/// `.`, and any pointer can be cast to one.
+const MANHATTAN_LENGTH_CODE: &str = r#"
+    fn manhattan_length() -> (r) {
+        p = &offset as &Point
+        r = (if p.x < 0 { -p.x } else { p.x }) + (if p.y < 0 { -p.y } else { p.y })
+    }
+"#;
+
+/// A global variable keeps its value between calls, while a constant is
+/// replaced by its value wherever it's used.
+const NEXT_TICKET_CODE: &str = r#"
+    global tickets = 0
+    const FIRST_TICKET = 100
+
+    fn next_ticket() -> (r) {
+        r = FIRST_TICKET + tickets
+        tickets = tickets + 1
+    }
+"#;
+
+/// A `match` runs the arm whose pattern equals its value. When the patterns
+/// are close together, like the opcodes of this little stack machine, it
+/// dispatches through a jump table rather than comparing against each one.
+const STACK_MACHINE_CODE: &str = r#"
+    const PUSH = 0
+    const ADD = 1
+    const MUL = 2
+    const HALT = 3
+
+    fn stack_machine() -> (r) {
+        // (2 + 3) * 4
+        let code[9]
+        code[0] = PUSH; code[1] = 2
+        code[2] = PUSH; code[3] = 3
+        code[4] = ADD
+        code[5] = PUSH; code[6] = 4
+        code[7] = MUL
+        code[8] = HALT
+
+        let stack[4]
+        pc = 0
+        sp = 0
+        running = true
+        while running {
+            op = code[pc]
+            pc = pc + 1
+            match op {
+                PUSH => {
+                    stack[sp] = code[pc]
+                    pc = pc + 1
+                    sp = sp + 1
+                }
+                ADD | MUL => {
+                    sp = sp - 1
+                    a = stack[sp - 1]
+                    b = stack[sp]
+                    stack[sp - 1] = match op { ADD => a + b, _ => a * b }
+                }
+                _ => running = false,
+            }
+        }
+        r = stack[0]
+    }
+"#;
+
 /// Let's say hello, by calling into libc. The puts function is resolved by
-/// dlsym to the libc function, and the string &hello_string is defined below.
+/// dlsym to the libc function, and the string literal evaluates to the
+/// address of a NUL-terminated copy of it.
 const HELLO_CODE: &str = r#"
 fn hello() -> (r) {
-    puts(&hello_string)
+    puts("hello world!")
 }
 "#;
@@ -0,0 +1,147 @@
+//! Conversions between Rust values and the values passed to and returned
+//! from JIT'd functions by `JIT::call`.
+//!
+//! Arguments and results are passed through an array of 64-bit slots, one
+//! per value, so that a function with any signature can be called the same
+//! way.
+
+use crate::frontend::Ty;
+
+/// A type which can be passed to or returned from a JIT'd function.
+pub trait Scalar: Sized {
+    /// The corresponding type in the toy language.
+    const TY: Ty;
+
+    /// Convert the value to the contents of a slot.
+    fn to_slot(self) -> u64;
+
+    /// Convert the contents of a slot back into a value.
+    fn from_slot(slot: u64) -> Self;
+}
+
+macro_rules! impl_scalar_int {
+    ($($rust:ty => $ty:ident),*) => {
+        $(
+            impl Scalar for $rust {
+                const TY: Ty = Ty::$ty;
+
+                fn to_slot(self) -> u64 {
+                    self as u64
+                }
+
+                fn from_slot(slot: u64) -> Self {
+                    slot as $rust
+                }
+            }
+        )*
+    };
+}
+
+impl_scalar_int!(i8 => I8, i16 => I16, i32 => I32, i64 => I64);
+
+impl Scalar for f32 {
+    const TY: Ty = Ty::F32;
+
+    fn to_slot(self) -> u64 {
+        self.to_bits().into()
+    }
+
+    fn from_slot(slot: u64) -> Self {
+        f32::from_bits(slot as u32)
+    }
+}
+
+impl Scalar for f64 {
+    const TY: Ty = Ty::F64;
+
+    fn to_slot(self) -> u64 {
+        self.to_bits()
+    }
+
+    fn from_slot(slot: u64) -> Self {
+        f64::from_bits(slot)
+    }
+}
+
+impl Scalar for bool {
+    const TY: Ty = Ty::Bool;
+
+    fn to_slot(self) -> u64 {
+        self.into()
+    }
+
+    fn from_slot(slot: u64) -> Self {
+        slot as u8 != 0
+    }
+}
+
+impl<T> Scalar for *const T {
+    const TY: Ty = Ty::Ptr;
+
+    fn to_slot(self) -> u64 {
+        self as usize as u64
+    }
+
+    fn from_slot(slot: u64) -> Self {
+        slot as usize as *const T
+    }
+}
+
+impl<T> Scalar for *mut T {
+    const TY: Ty = Ty::Ptr;
+
+    fn to_slot(self) -> u64 {
+        self as usize as u64
+    }
+
+    fn from_slot(slot: u64) -> Self {
+        slot as usize as *mut T
+    }
+}
+
+/// The arguments to a JIT'd function, as a tuple.
+pub trait Args {
+    /// The types of the arguments.
+    const TYPES: &'static [Ty];
+
+    /// Convert the arguments to slots.
+    fn to_slots(self) -> Vec<u64>;
+}
+
+/// The results of a JIT'd function, as a tuple.
+pub trait Results: Sized {
+    /// The types of the results.
+    const TYPES: &'static [Ty];
+
+    /// Convert slots back into the results.
+    fn from_slots(slots: &[u64]) -> Self;
+}
+
+macro_rules! impl_tuple {
+    ($($name:ident $index:tt),*) => {
+        impl<$($name: Scalar),*> Args for ($($name,)*) {
+            const TYPES: &'static [Ty] = &[$($name::TY),*];
+
+            fn to_slots(self) -> Vec<u64> {
+                vec![$(self.$index.to_slot()),*]
+            }
+        }
+
+        impl<$($name: Scalar),*> Results for ($($name,)*) {
+            const TYPES: &'static [Ty] = &[$($name::TY),*];
+
+            #[allow(unused_variables, clippy::unused_unit)]
+            fn from_slots(slots: &[u64]) -> Self {
+                ($($name::from_slot(slots[$index]),)*)
+            }
+        }
+    };
+}
+
+impl_tuple!();
+impl_tuple!(A 0);
+impl_tuple!(A 0, B 1);
+impl_tuple!(A 0, B 1, C 2);
+impl_tuple!(A 0, B 1, C 2, D 3);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
@@ -0,0 +1,323 @@
+use crate::frontend::{Span, Ty};
+use crate::runtime::Trap;
+use cranelift::codegen::CodegenError;
+use cranelift::codegen::verifier::VerifierErrors;
+use cranelift_module::ModuleError;
+use peg::error::ParseError;
+use peg::str::LineCol;
+use std::error::Error;
+use std::fmt;
+
+/// The errors that can occur while compiling and running toy-language code.
+#[derive(Debug)]
+pub enum JitError {
+    /// The input is not syntactically valid.
+    Parse {
+        line: usize,
+        column: usize,
+        offset: usize,
+        expected: String,
+    },
+
+    /// A variable was read or assigned without being declared.
+    UndefinedVariable { name: String, span: Span },
+
+    /// A literal doesn't fit in the type of the value it produces.
+    LiteralOutOfRange { literal: String, span: Span },
+
+    /// A function was called with the wrong number of arguments.
+    ArityMismatch {
+        name: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+
+    /// An expression has a different type from the one its context requires.
+    TypeMismatch { expected: Ty, found: Ty, span: Span },
+
+    /// An operator was applied to a value of a type it doesn't support.
+    InvalidOperand { op: String, ty: Ty, span: Span },
+
+    /// An `as` cast between types which can't be converted to each other.
+    InvalidCast { from: Ty, to: Ty, span: Span },
+
+    /// An expression which doesn't produce a value, such as a loop, or an
+    /// `if` whose branches produce values of different types, was used
+    /// where a value is needed.
+    NoValue { span: Span },
+
+    /// A local array was declared with the same name as another array or
+    /// variable, a loop variable with the same name as a global, or a
+    /// function, struct, field or global with the same name as another.
+    Redefinition { name: String, span: Span },
+
+    /// A local array was declared somewhere other than at the top level of
+    /// a function body, such as in a branch of an `if`.
+    NestedArray { span: Span },
+
+    /// A function was defined or called with a name which is reserved for
+    /// the runtime support routines.
+    ReservedName { name: String, span: Span },
+
+    /// A constant was assigned to.
+    AssignToConstant { name: String, span: Span },
+
+    /// The address of a constant was taken with `&`.
+    AddressOfConstant { name: String, span: Span },
+
+    /// A struct which hasn't been declared was used.
+    UndefinedStruct { name: String, span: Span },
+
+    /// A field was accessed which the struct doesn't have.
+    UndefinedField {
+        name: String,
+        field: String,
+        span: Span,
+    },
+
+    /// A value which has to be known at compile time, such as a field of a
+    /// struct literal, isn't a constant.
+    NotConstant { span: Span },
+
+    /// A pattern in a `match` can never match, because an earlier pattern
+    /// matches the same value or is `_`.
+    UnreachablePattern { span: Span },
+
+    /// A function which doesn't exist was called or declared with `extern
+    /// fn` by a script, in which case the error has the span of the call or
+    /// declaration, or was called by `JIT::call`.
+    UndefinedFunction { name: String, span: Option<Span> },
+
+    /// `JIT::call` was asked to call a function with arguments or results
+    /// which don't match its signature.
+    SignatureMismatch {
+        name: String,
+        expected: String,
+        found: String,
+    },
+
+    /// Declaring, defining or finalizing a function or data object failed,
+    /// for example because the same name was defined twice.
+    Module(Box<ModuleError>),
+
+    /// Cranelift failed to generate machine code for a function.
+    Codegen(CodegenError),
+
+    /// The Cranelift IR generated for a function is invalid. This always
+    /// indicates a bug in the translation from the toy language.
+    Verifier(VerifierErrors),
+
+    /// A `return` or tuple assignment has a different number of values than
+    /// the function returns or the call produces.
+    ValueCountMismatch {
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+
+    /// The step of a `for` loop is zero or negative, so the loop would
+    /// never reach the end of its range.
+    InvalidStep { span: Span },
+
+    /// A `break` or `continue` appears outside of any loop.
+    BreakOutsideLoop { span: Span },
+
+    /// A `break` or `continue` refers to a label which isn't on any
+    /// enclosing loop.
+    UndefinedLabel { label: String, span: Span },
+
+    /// JIT'd code raised a runtime error.
+    Trap(Trap),
+}
+
+impl fmt::Display for JitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JitError::Parse {
+                line,
+                column,
+                expected,
+                ..
+            } => write!(f, "parse error at {line}:{column}: expected {expected}"),
+            JitError::UndefinedVariable { name, .. } => write!(f, "undefined variable `{name}`"),
+            JitError::LiteralOutOfRange { literal, .. } => {
+                write!(f, "literal `{literal}` is out of range")
+            }
+            JitError::ArityMismatch {
+                name,
+                expected,
+                found,
+                ..
+            } => write!(
+                f,
+                "function `{name}` takes {expected} argument(s) but {found} were supplied"
+            ),
+            JitError::ValueCountMismatch {
+                expected, found, ..
+            } => write!(f, "expected {expected} value(s) but found {found}"),
+            JitError::InvalidStep { .. } => write!(f, "the step of a range must be positive"),
+            JitError::BreakOutsideLoop { .. } => {
+                write!(f, "`break` or `continue` outside of a loop")
+            }
+            JitError::UndefinedLabel { label, .. } => {
+                write!(f, "use of undeclared label `'{label}`")
+            }
+            JitError::TypeMismatch {
+                expected, found, ..
+            } => write!(
+                f,
+                "mismatched types: expected `{expected}`, found `{found}`"
+            ),
+            JitError::InvalidOperand { op, ty, .. } => {
+                write!(f, "{op} is not supported on `{ty}`")
+            }
+            JitError::InvalidCast { from, to, .. } => {
+                write!(f, "cannot cast `{from}` as `{to}`")
+            }
+            JitError::NoValue { .. } => write!(f, "this expression doesn't produce a value"),
+            JitError::Redefinition { name, .. } => write!(f, "`{name}` is defined more than once"),
+            JitError::NestedArray { .. } => write!(
+                f,
+                "arrays can only be declared at the top level of a function body"
+            ),
+            JitError::ReservedName { name, .. } => {
+                write!(f, "the name `{name}` is reserved for the runtime")
+            }
+            JitError::AssignToConstant { name, .. } => {
+                write!(f, "cannot assign to constant `{name}`")
+            }
+            JitError::AddressOfConstant { name, .. } => {
+                write!(f, "cannot take the address of constant `{name}`")
+            }
+            JitError::UndefinedStruct { name, .. } => write!(f, "undefined struct `{name}`"),
+            JitError::UndefinedField { name, field, .. } => {
+                write!(f, "no field `{field}` on struct `{name}`")
+            }
+            JitError::NotConstant { .. } => write!(f, "this expression isn't a constant"),
+            JitError::UnreachablePattern { .. } => write!(f, "unreachable pattern"),
+            JitError::UndefinedFunction { name, .. } => write!(f, "undefined function `{name}`"),
+            JitError::SignatureMismatch {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "function `{name}` has signature `{expected}` but was called as `{found}`"
+            ),
+            JitError::Module(err) => write!(f, "module error: {err}"),
+            JitError::Codegen(err) => write!(f, "codegen error: {err}"),
+            JitError::Verifier(errors) => write!(f, "verifier error: {errors}"),
+            JitError::Trap(trap) => write!(f, "runtime error: {trap}"),
+        }
+    }
+}
+
+impl JitError {
+    /// The span of source text that the error refers to, if any.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            JitError::Parse { offset, .. } => Some(Span {
+                start: *offset,
+                end: offset + 1,
+            }),
+            JitError::UndefinedVariable { span, .. }
+            | JitError::LiteralOutOfRange { span, .. }
+            | JitError::ArityMismatch { span, .. }
+            | JitError::ValueCountMismatch { span, .. }
+            | JitError::InvalidStep { span }
+            | JitError::BreakOutsideLoop { span }
+            | JitError::UndefinedLabel { span, .. }
+            | JitError::TypeMismatch { span, .. }
+            | JitError::InvalidOperand { span, .. }
+            | JitError::InvalidCast { span, .. }
+            | JitError::NoValue { span }
+            | JitError::Redefinition { span, .. }
+            | JitError::NestedArray { span }
+            | JitError::ReservedName { span, .. }
+            | JitError::AssignToConstant { span, .. }
+            | JitError::AddressOfConstant { span, .. }
+            | JitError::UndefinedStruct { span, .. }
+            | JitError::UndefinedField { span, .. }
+            | JitError::NotConstant { span }
+            | JitError::UnreachablePattern { span } => Some(*span),
+            JitError::UndefinedFunction { span, .. } => *span,
+            _ => None,
+        }
+    }
+
+    /// Render the error as a rustc-style diagnostic, quoting the line of
+    /// `source` that the error refers to and underlining the offending span.
+    /// `source` must be the text that was passed to the JIT.
+    pub fn render(&self, source: &str) -> String {
+        let Some(span) = self.span() else {
+            return format!("error: {self}\n");
+        };
+        let start = span.start.min(source.len());
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |i| start + i);
+        let line = source[line_start..line_end].trim_end_matches('\r');
+        let line_number = source[..start].matches('\n').count() + 1;
+        let column = source[line_start..start].chars().count() + 1;
+
+        // Indent the carets the same way as the quoted line, so that they
+        // still line up when it contains tabs.
+        let indent: String = source[line_start..start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        // A parse error's span covers a single byte, which may be part of a
+        // longer character, so round the end up to the end of that character.
+        let end = span.end.clamp(start, line_end);
+        let end = (end..=line_end)
+            .find(|&i| source.is_char_boundary(i))
+            .unwrap_or(line_end);
+        let width = source[start..end].chars().count().max(1);
+
+        let gutter = " ".repeat(line_number.to_string().len());
+        format!(
+            "error: {self}\n\
+             {gutter}--> {line_number}:{column}\n\
+             {gutter} |\n\
+             {line_number} | {line}\n\
+             {gutter} | {indent}{carets}\n",
+            carets = "^".repeat(width),
+        )
+    }
+}
+
+impl Error for JitError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            JitError::Module(err) => Some(&**err),
+            JitError::Codegen(err) => Some(err),
+            JitError::Verifier(errors) => Some(errors),
+            _ => None,
+        }
+    }
+}
+
+impl From<ParseError<LineCol>> for JitError {
+    fn from(err: ParseError<LineCol>) -> Self {
+        JitError::Parse {
+            line: err.location.line,
+            column: err.location.column,
+            offset: err.location.offset,
+            expected: err.expected.to_string(),
+        }
+    }
+}
+
+impl From<ModuleError> for JitError {
+    fn from(err: ModuleError) -> Self {
+        // Codegen failures surface through the module when a function is
+        // defined, so unwrap them to let callers tell them apart.
+        match err {
+            ModuleError::Compilation(CodegenError::Verifier(errors)) => JitError::Verifier(errors),
+            ModuleError::Compilation(err) => JitError::Codegen(err),
+            err => JitError::Module(Box::new(err)),
+        }
+    }
+}
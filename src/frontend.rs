@@ -1,87 +1,724 @@
-/// The AST node for expressions.
-pub enum Expr {
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::Mutex;
+
+/// A range of byte offsets into the source text.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// The types of values in the toy language. Anything which isn't annotated
+/// with a type is an `i64`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ty {
+    I8,
+    I16,
+    I32,
+    I64,
+    F32,
+    F64,
+    Bool,
+    /// A pointer, which is as wide as the host's pointers.
+    Ptr,
+    /// A pointer to the struct with the given name, as in `&Point`, through
+    /// which its fields can be accessed.
+    Ref(&'static str),
+}
+
+impl Ty {
+    pub fn is_integer(self) -> bool {
+        matches!(self, Ty::I8 | Ty::I16 | Ty::I32 | Ty::I64)
+    }
+
+    pub fn is_float(self) -> bool {
+        matches!(self, Ty::F32 | Ty::F64)
+    }
+
+    pub fn is_pointer(self) -> bool {
+        matches!(self, Ty::Ptr | Ty::Ref(_))
+    }
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Ty::I8 => "i8",
+            Ty::I16 => "i16",
+            Ty::I32 => "i32",
+            Ty::I64 => "i64",
+            Ty::F32 => "f32",
+            Ty::F64 => "f64",
+            Ty::Bool => "bool",
+            Ty::Ptr => "ptr",
+            Ty::Ref(name) => return write!(f, "&{name}"),
+        };
+        f.write_str(name)
+    }
+}
+
+/// The AST node for expressions, along with the span of source text it was
+/// parsed from.
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+
+    /// The type of the value the expression produces, which is filled in by
+    /// type checking. This is `None` for expressions which don't produce a
+    /// value, such as loops.
+    pub ty: Option<Ty>,
+}
+
+/// The different kinds of expressions.
+pub enum ExprKind {
     Literal(String),
+    Float(String),
+    Bool(bool),
+    /// A string literal, which evaluates to the address of a NUL-terminated
+    /// copy of the string in read-only memory.
+    Str(String),
     Identifier(String),
-    Assign(String, Box<Expr>),
+    /// Assign a value to a variable, which may be annotated with a type, as
+    /// in `x: i32 = 5`.
+    Assign(String, Option<Ty>, Box<Expr>),
+    /// Assign each of the values returned by a call to the corresponding
+    /// variable, as in `(q, r) = divmod(a, b)`.
+    TupleAssign(Vec<String>, Box<Expr>),
+    /// Declare a local array with the given number of elements of the given
+    /// type, as in `let buf[64]: i32`. Its elements are zeroed each time the
+    /// declaration runs. It can only appear as a statement at the top level
+    /// of a function body, so that it has always run before the array is
+    /// used.
+    ArrayDecl(String, Ty, u32),
+    /// Read an element of a local array, as in `buf[i]`.
+    Index(String, Box<Expr>),
+    /// Write a value to an element of a local array, as in `buf[i] = x`.
+    IndexAssign(String, Box<Expr>, Box<Expr>),
     Eq(Box<Expr>, Box<Expr>),
     Ne(Box<Expr>, Box<Expr>),
     Lt(Box<Expr>, Box<Expr>),
     Le(Box<Expr>, Box<Expr>),
     Gt(Box<Expr>, Box<Expr>),
     Ge(Box<Expr>, Box<Expr>),
+    ULt(Box<Expr>, Box<Expr>),
+    ULe(Box<Expr>, Box<Expr>),
+    UGt(Box<Expr>, Box<Expr>),
+    UGe(Box<Expr>, Box<Expr>),
     Add(Box<Expr>, Box<Expr>),
     Sub(Box<Expr>, Box<Expr>),
     Mul(Box<Expr>, Box<Expr>),
     Div(Box<Expr>, Box<Expr>),
-    IfElse(Box<Expr>, Vec<Expr>, Vec<Expr>),
-    WhileLoop(Box<Expr>, Vec<Expr>),
+    Rem(Box<Expr>, Box<Expr>),
+    UDiv(Box<Expr>, Box<Expr>),
+    URem(Box<Expr>, Box<Expr>),
+    BitAnd(Box<Expr>, Box<Expr>),
+    BitOr(Box<Expr>, Box<Expr>),
+    BitXor(Box<Expr>, Box<Expr>),
+    Shl(Box<Expr>, Box<Expr>),
+    Shr(Box<Expr>, Box<Expr>),
+    UShr(Box<Expr>, Box<Expr>),
+    Neg(Box<Expr>),
+    BitNot(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    /// Convert a value to another type, as in `x as i32`.
+    Cast(Box<Expr>, Ty),
+    /// A chain of `if` and `else if` branches, each with a condition and a
+    /// body, followed by the body of the final `else`, which is empty if
+    /// there isn't one.
+    IfElse(Vec<(Expr, Vec<Expr>)>, Vec<Expr>),
+    /// Compare a value against the patterns of each arm in turn, and run
+    /// the body of the first arm with a pattern that matches, as in
+    /// `match x { 0 => a, 1 | 2 => b, _ => c }`. If no arm matches, the
+    /// result is the same as that of an empty body.
+    Match(Box<Expr>, Vec<MatchArm>),
+    /// A loop, with an optional label which `break` and `continue` can use
+    /// to refer to it from within a nested loop.
+    WhileLoop(Option<String>, Box<Expr>, Vec<Expr>),
+    /// A loop over the range `start..end`, or `start..=end` if `inclusive`
+    /// is set, counting up by `step`, or by 1 if there's no step. The bounds
+    /// and step are evaluated once, before the first iteration. The loop
+    /// stops before the variable would overflow, and runs no iterations if
+    /// the step isn't positive.
+    ForLoop {
+        label: Option<String>,
+        var: String,
+        start: Box<Expr>,
+        end: Box<Expr>,
+        inclusive: bool,
+        step: Option<Box<Expr>>,
+        body: Vec<Expr>,
+    },
+    Break(Option<String>),
+    Continue(Option<String>),
+    /// Return from the function, either with the given values or, if there
+    /// aren't any, with the values of the return variables.
+    Return(Vec<Expr>),
     Call(String, Vec<Expr>),
     GlobalDataAddr(String),
+    /// Read a field of the struct which the expression points to, as in
+    /// `p.x`.
+    Field(Box<Expr>, String),
+    /// Write the value of the second expression to a field of the struct
+    /// which the first points to, as in `p.x = 5`.
+    FieldAssign(Box<Expr>, String, Box<Expr>),
+    /// Read a value of the given type from the address produced by the
+    /// expression, as in `load32(p)`.
+    Load(Ty, Box<Expr>),
+    /// Write the value of the second expression, which has the given type,
+    /// to the address produced by the first, as in `store32(p, x)`.
+    Store(Ty, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// Build a prefix expression starting at `start`.
+    fn unary(op: fn(Box<Expr>) -> ExprKind, start: usize, operand: Expr) -> Expr {
+        let span = Span {
+            start,
+            end: operand.span.end,
+        };
+        Expr {
+            kind: op(Box::new(operand)),
+            span,
+            ty: None,
+        }
+    }
+
+    /// Build a binary expression spanning both of its operands.
+    fn binary(op: fn(Box<Expr>, Box<Expr>) -> ExprKind, lhs: Expr, rhs: Expr) -> Expr {
+        let span = Span {
+            start: lhs.span.start,
+            end: rhs.span.end,
+        };
+        Expr {
+            kind: op(Box::new(lhs), Box::new(rhs)),
+            span,
+            ty: None,
+        }
+    }
+
+    /// Build a cast of `operand` to `ty`, ending at `end`.
+    fn cast(operand: Expr, ty: Ty, end: usize) -> Expr {
+        let span = Span {
+            start: operand.span.start,
+            end,
+        };
+        Expr {
+            kind: ExprKind::Cast(Box::new(operand), ty),
+            span,
+            ty: None,
+        }
+    }
+
+    /// Build an access to the field `field` of `object`, ending at `end`.
+    fn field(object: Expr, field: String, end: usize) -> Expr {
+        let span = Span {
+            start: object.span.start,
+            end,
+        };
+        Expr {
+            kind: ExprKind::Field(Box::new(object), field),
+            span,
+            ty: None,
+        }
+    }
+}
+
+/// An arm of a `match`, which runs `body` if any of `patterns` matches.
+pub struct MatchArm {
+    pub patterns: Vec<Pattern>,
+    pub body: Vec<Expr>,
+}
+
+/// A pattern in an arm of a `match`.
+pub enum Pattern {
+    /// `_`, which matches any value.
+    Wildcard(Span),
+    /// A constant, which matches a value equal to it.
+    Value(Expr),
+}
+
+impl Pattern {
+    pub fn span(&self) -> Span {
+        match self {
+            Pattern::Wildcard(span) => *span,
+            Pattern::Value(expr) => expr.span,
+        }
+    }
+}
+
+/// A function definition: its name, the names and types of its parameters
+/// and return variables, and the statements making up its body.
+pub struct Function {
+    pub name: String,
+    pub params: Vec<(String, Ty)>,
+    pub returns: Vec<(String, Ty)>,
+    pub stmts: Vec<Expr>,
+    /// The span of the function's name.
+    pub span: Span,
+}
+
+/// The declaration of a function provided by the host, such as one from
+/// libc, as in `extern fn cos(x: f64) -> (r: f64)`, which gives the types of
+/// its parameters and return values so that it can be called with them.
+pub struct Extern {
+    pub name: String,
+    pub params: Vec<(String, Ty)>,
+    pub returns: Vec<(String, Ty)>,
+    /// The span of the function's name.
+    pub span: Span,
+}
+
+/// A struct declaration: its name, and the names and types of its fields in
+/// the order in which they're laid out in memory.
+pub struct Struct {
+    pub name: String,
+    pub fields: Vec<(String, Ty)>,
+    pub span: Span,
+}
+
+/// A global variable, as in `global counter = 0`, or, if `constant` is set,
+/// a constant, as in `const LIMIT = 100`. Either may be annotated with a
+/// type, and its value must be a constant.
+pub struct Global {
+    pub name: String,
+    pub ty: Option<Ty>,
+    pub value: Expr,
+    pub constant: bool,
+    pub span: Span,
+}
+
+/// A source unit, containing the structs, external functions, globals and
+/// functions it declares.
+pub struct Program {
+    pub structs: Vec<Struct>,
+    pub externs: Vec<Extern>,
+    pub globals: Vec<Global>,
+    pub functions: Vec<Function>,
+}
+
+/// The top-level declarations which can appear in a program.
+enum Item {
+    Struct(Struct),
+    Extern(Extern),
+    Global(Global),
+    Function(Function),
+}
+
+/// A struct literal, which gives the values of the fields of a struct, as in
+/// `Point { x: 1, y: 2 }`.
+pub struct StructLiteral {
+    pub name: String,
+    pub fields: Vec<(String, Expr)>,
+    pub span: Span,
+}
+
+/// Intern the name of a struct, so that types referring to it can be
+/// `Copy`. Each distinct name is only allocated once, and never freed.
+fn intern(name: &str) -> &'static str {
+    static NAMES: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+    let mut names = NAMES.lock().unwrap();
+    if let Some(name) = names.get(name) {
+        return name;
+    }
+    let name = Box::leak(name.into());
+    names.insert(name);
+    name
 }
 
 peg::parser!(pub grammar parser() for str {
-    pub rule function() -> (String, Vec<String>, String, Vec<Expr>)
-        = [' ' | '\t' | '\n']* "fn" _ name:identifier() _
-        "(" params:((_ i:identifier() _ {i}) ** ",") ")" _
-        "->" _
-        "(" returns:(_ i:identifier() _ {i}) ")" _
-        "{" _ "\n"
-        stmts:statements()
-        _ "}" _ "\n" _
-        { (name, params, returns, stmts) }
+    /// A source unit containing any number of structs, external functions,
+    /// globals and functions.
+    pub rule program() -> Program
+        = items:item()* __
+        {
+            let mut program = Program {
+                structs: Vec::new(),
+                externs: Vec::new(),
+                globals: Vec::new(),
+                functions: Vec::new(),
+            };
+            for item in items {
+                match item {
+                    Item::Struct(s) => program.structs.push(s),
+                    Item::Extern(e) => program.externs.push(e),
+                    Item::Global(g) => program.globals.push(g),
+                    Item::Function(f) => program.functions.push(f),
+                }
+            }
+            program
+        }
+
+    rule item() -> Item
+        = s:struct_declaration() { Item::Struct(s) }
+        / e:extern_declaration() { Item::Extern(e) }
+        / g:global_declaration() { Item::Global(g) }
+        / f:function() { Item::Function(f) }
 
+    rule global_declaration() -> Global
+        = __ start:position!()
+        constant:("global" !ident_char() { false } / "const" !ident_char() { true }) __
+        name:identifier() ty:annotation()? _ "=" !"=" __ value:expression() end:position!()
+        { Global { name, ty, value, constant, span: Span { start, end } } }
+
+    rule extern_declaration() -> Extern
+        = __ "extern" !ident_char() __ "fn" !ident_char() __
+        start:position!() name:identifier() end:position!() __
+        "(" params:comma_list(<typed_name()>) ")" __
+        "->" __
+        "(" returns:comma_list(<typed_name()>) ")" __
+        { Extern { name, params, returns, span: Span { start, end } } }
+
+    rule struct_declaration() -> Struct
+        = __ start:position!() "struct" !ident_char() __ name:identifier() __
+        "{" fields:comma_list(<field()>) "}" end:position!() __
+        { Struct { name, fields, span: Span { start, end } } }
+
+    rule field() -> (String, Ty)
+        = name:identifier() t:annotation() { (name, t) }
+
+    /// A struct literal, whose field values must be constants.
+    pub rule struct_literal() -> StructLiteral
+        = __ start:position!() name:identifier() __
+        "{" fields:comma_list(<field_value()>) "}" end:position!() __
+        { StructLiteral { name, fields, span: Span { start, end } } }
+
+    rule field_value() -> (String, Expr)
+        = name:identifier() _ ":" __ value:expression() { (name, value) }
+
+    pub rule function() -> Function
+        = __ "fn" !ident_char() __ start:position!() name:identifier() end:position!() __
+        "(" params:comma_list(<typed_name()>) ")" __
+        "->" __
+        "(" returns:comma_list(<typed_name()>) ")" __
+        "{" stmts:statements() "}" __
+        { Function { name, params, returns, stmts, span: Span { start, end } } }
+
+    /// A parameter or return variable, with an optional type annotation.
+    rule typed_name() -> (String, Ty)
+        = name:identifier() ty:annotation()? { (name, ty.unwrap_or(Ty::I64)) }
+
+    rule annotation() -> Ty
+        = _ ":" __ t:ty() { t }
+
+    rule ty() -> Ty
+        = quiet!{ t:(
+            "i8" { Ty::I8 }
+            / "i16" { Ty::I16 }
+            / "i32" { Ty::I32 }
+            / "i64" { Ty::I64 }
+            / "f32" { Ty::F32 }
+            / "f64" { Ty::F64 }
+            / "bool" { Ty::Bool }
+            / "ptr" { Ty::Ptr }
+        ) !ident_char() { t } }
+        / quiet!{ "&" _ name:identifier() { Ty::Ref(intern(&name)) } }
+        / expected!("type")
+
+    /// A sequence of statements, each of which is ended by a newline, a `;`,
+    /// or the `}` closing the enclosing block.
     rule statements() -> Vec<Expr>
-        = s:(statement()*) { s }
+        = s:(blank() e:expression() end_of_statement() { e })* blank() { s }
 
-    rule statement() -> Expr
-        = _ e:expression() _ "\n" { e }
+    rule end_of_statement()
+        = _ (";" / "\n" / &"}")
+
+    /// Anything which can separate statements, including empty statements.
+    rule blank()
+        = __ (";" __)*
 
     rule expression() -> Expr
         = if_else()
+        / match_expr()
         / while_loop()
+        / for_loop()
+        / break_continue()
+        / return_expr()
+        / tuple_assignment()
+        / array_declaration()
+        / index_assignment()
+        / field_assignment()
         / assignment()
         / binary_op()
 
     rule if_else() -> Expr
-        = "if" _ e:expression() _ "{" _ "\n"
-        then_body:statements() _ "}" _ "else" _ "{" _ "\n"
-        else_body:statements() _ "}"
-        { Expr::IfElse(Box::new(e), then_body, else_body) }
+        = spanned(<"if" !ident_char() __ first:if_branch()
+        rest:(__ "else" !ident_char() __ "if" !ident_char() __ b:if_branch() { b })*
+        else_body:(__ "else" !ident_char() __ "{" s:statements() "}" { s })?
+        {
+            let mut branches = vec![first];
+            branches.extend(rest);
+            ExprKind::IfElse(branches, else_body.unwrap_or_default())
+        }>)
+
+    rule if_branch() -> (Expr, Vec<Expr>)
+        = e:expression() __ "{" body:statements() "}" { (e, body) }
+
+    rule match_expr() -> Expr
+        = spanned(<"match" !ident_char() __ scrutinee:expression() __ "{"
+        arms:(__ a:match_arm() _ ","? { a })* __ "}"
+        { ExprKind::Match(Box::new(scrutinee), arms) }>)
+
+    rule match_arm() -> MatchArm
+        = patterns:(pattern() ++ (__ "|" __)) __ "=>" __ body:arm_body()
+        { MatchArm { patterns, body } }
+
+    /// The body of an arm, which is either a block or a single expression.
+    rule arm_body() -> Vec<Expr>
+        = "{" s:statements() "}" { s }
+        / e:expression() { vec![e] }
+
+    /// A pattern, which is `_` or a constant: a literal, which may be
+    /// negated, or the name of a `const`.
+    rule pattern() -> Pattern
+        = start:position!() "_" !ident_char() end:position!() { Pattern::Wildcard(Span { start, end }) }
+        / start:position!() "-" _ l:literal() { Pattern::Value(Expr::unary(ExprKind::Neg, start, l)) }
+        / l:literal() { Pattern::Value(l) }
+        / e:spanned(<i:identifier() { ExprKind::Identifier(i) }>) { Pattern::Value(e) }
 
     rule while_loop() -> Expr
-        = "while" _ e:expression() _ "{" _ "\n"
-        loop_body:statements() _ "}"
-        { Expr::WhileLoop(Box::new(e), loop_body) }
+        = spanned(<label:(l:label() _ ":" __ { l })? "while" !ident_char() __ e:expression() __
+        "{" loop_body:statements() "}"
+        { ExprKind::WhileLoop(label, Box::new(e), loop_body) }>)
+
+    rule for_loop() -> Expr
+        = spanned(<label:(l:label() _ ":" __ { l })? "for" !ident_char() __
+        var:identifier() __ "in" !ident_char() __
+        start:expression() __ ".." inclusive:"="? __ end:expression()
+        step:(__ "step" !ident_char() __ s:expression() { Box::new(s) })? __
+        "{" body:statements() "}"
+        {
+            ExprKind::ForLoop {
+                label,
+                var,
+                start: Box::new(start),
+                end: Box::new(end),
+                inclusive: inclusive.is_some(),
+                step,
+                body,
+            }
+        }>)
+
+    rule break_continue() -> Expr
+        = spanned(<"break" !ident_char() label:(_ l:label() { l })? { ExprKind::Break(label) }>)
+        / spanned(<"continue" !ident_char() label:(_ l:label() { l })? { ExprKind::Continue(label) }>)
+
+    rule return_expr() -> Expr
+        = spanned(<"return" !ident_char() _ values:(expression() ** (_ "," __)) { ExprKind::Return(values) }>)
+
+    rule label() -> String
+        = "'" i:identifier() { i }
 
     rule assignment() -> Expr
-        = i:identifier() _ "=" _ e:expression() {Expr::Assign(i, Box::new(e))}
+        = spanned(<i:identifier() ty:annotation()? _ "=" !"=" __ e:expression()
+        { ExprKind::Assign(i, ty, Box::new(e)) }>)
 
+    /// A local array, whose elements are `i64`s unless it's annotated with
+    /// another type.
+    rule array_declaration() -> Expr
+        = spanned(<"let" !ident_char() __ i:identifier() _ "[" __ len:array_length() __ "]"
+        ty:annotation()?
+        { ExprKind::ArrayDecl(i, ty.unwrap_or(Ty::I64), len) }>)
+
+    rule array_length() -> u32
+        = quiet!{ n:$(['1'..='9'] ['0'..='9']*) {? n.parse().or(Err("array length")) } }
+        / expected!("array length")
+
+    rule index_assignment() -> Expr
+        = spanned(<i:identifier() _ "[" __ index:expression() __ "]" _ "=" !"=" __ e:expression()
+        { ExprKind::IndexAssign(i, Box::new(index), Box::new(e)) }>)
+
+    /// An assignment to a field, possibly of a struct reached through the
+    /// fields of others, as in `node.next.value = 5`.
+    rule field_assignment() -> Expr
+        = spanned(<start:position!() first:identifier() end:position!()
+        path:("." f:identifier() end:position!() { (f, end) })+
+        _ "=" !"=" __ e:expression()
+        {
+            let mut object = Expr {
+                kind: ExprKind::Identifier(first),
+                span: Span { start, end },
+                ty: None,
+            };
+            let mut path = path;
+            let (field, _) = path.pop().unwrap();
+            for (f, end) in path {
+                object = Expr::field(object, f, end);
+            }
+            ExprKind::FieldAssign(Box::new(object), field, Box::new(e))
+        }>)
+
+    rule tuple_assignment() -> Expr
+        = spanned(<"(" names:comma_list(<identifier()>) ")" _ "=" !"=" __ e:expression()
+        { ExprKind::TupleAssign(names, Box::new(e)) }>)
+
+    // Operators are listed from the loosest-binding to the tightest-binding,
+    // following the same precedence as Rust. Arithmetic and comparisons are
+    // signed; the unsigned versions are written as intrinsics, as in
+    // `udiv(a, b)`, apart from `>>>`, the unsigned shift right.
     rule binary_op() -> Expr = precedence!{
-        a:@ _ "==" _ b:(@) { Expr::Eq(Box::new(a), Box::new(b)) }
-        a:@ _ "!=" _ b:(@) { Expr::Ne(Box::new(a), Box::new(b)) }
-        a:@ _ "<"  _ b:(@) { Expr::Lt(Box::new(a), Box::new(b)) }
-        a:@ _ "<=" _ b:(@) { Expr::Le(Box::new(a), Box::new(b)) }
-        a:@ _ ">"  _ b:(@) { Expr::Gt(Box::new(a), Box::new(b)) }
-        a:@ _ ">=" _ b:(@) { Expr::Ge(Box::new(a), Box::new(b)) }
+        a:(@) _ "||" __ b:@ { Expr::binary(ExprKind::Or, a, b) }
+        --
+        a:(@) _ "&&" __ b:@ { Expr::binary(ExprKind::And, a, b) }
         --
-        a:@ _ "+" _ b:(@) { Expr::Add(Box::new(a), Box::new(b)) }
-        a:@ _ "-" _ b:(@) { Expr::Sub(Box::new(a), Box::new(b)) }
+        a:(@) _ "==" __ b:@ { Expr::binary(ExprKind::Eq, a, b) }
+        a:(@) _ "!=" __ b:@ { Expr::binary(ExprKind::Ne, a, b) }
+        a:(@) _ "<"  __ b:@ { Expr::binary(ExprKind::Lt, a, b) }
+        a:(@) _ "<=" __ b:@ { Expr::binary(ExprKind::Le, a, b) }
+        a:(@) _ ">"  __ b:@ { Expr::binary(ExprKind::Gt, a, b) }
+        a:(@) _ ">=" __ b:@ { Expr::binary(ExprKind::Ge, a, b) }
         --
-        a:@ _ "*" _ b:(@) { Expr::Mul(Box::new(a), Box::new(b)) }
-        a:@ _ "/" _ b:(@) { Expr::Div(Box::new(a), Box::new(b)) }
+        a:(@) _ "|" !"|" __ b:@ { Expr::binary(ExprKind::BitOr, a, b) }
         --
-        i:identifier() _ "(" args:((_ e:expression() _ {e}) ** ",") ")" { Expr::Call(i, args) }
-        i:identifier() { Expr::Identifier(i) }
+        a:(@) _ "^" __ b:@ { Expr::binary(ExprKind::BitXor, a, b) }
+        --
+        a:(@) _ "&" !"&" __ b:@ { Expr::binary(ExprKind::BitAnd, a, b) }
+        --
+        a:(@) _ "<<" __ b:@ { Expr::binary(ExprKind::Shl, a, b) }
+        a:(@) _ ">>>" __ b:@ { Expr::binary(ExprKind::UShr, a, b) }
+        a:(@) _ ">>" __ b:@ { Expr::binary(ExprKind::Shr, a, b) }
+        --
+        a:(@) _ "+" __ b:@ { Expr::binary(ExprKind::Add, a, b) }
+        a:(@) _ "-" __ b:@ { Expr::binary(ExprKind::Sub, a, b) }
+        --
+        a:(@) _ "*" __ b:@ { Expr::binary(ExprKind::Mul, a, b) }
+        a:(@) _ "/" __ b:@ { Expr::binary(ExprKind::Div, a, b) }
+        a:(@) _ "%" __ b:@ { Expr::binary(ExprKind::Rem, a, b) }
+        --
+        e:(@) _ "as" !ident_char() __ t:ty() end:position!() { Expr::cast(e, t, end) }
+        --
+        start:position!() "-" __ e:@ { Expr::unary(ExprKind::Neg, start, e) }
+        start:position!() "!" __ e:@ { Expr::unary(ExprKind::Not, start, e) }
+        start:position!() "~" __ e:@ { Expr::unary(ExprKind::BitNot, start, e) }
+        --
+        e:(@) "." f:identifier() end:position!() { Expr::field(e, f, end) }
+        --
+        e:spanned(<memory_access()>) { e }
+        e:spanned(<unsigned_op()>) { e }
+        e:spanned(<i:identifier() _ "(" args:comma_list(<expression()>) ")" { ExprKind::Call(i, args) }>) { e }
+        e:spanned(<i:identifier() _ "[" __ index:expression() __ "]" { ExprKind::Index(i, Box::new(index)) }>) { e }
         l:literal() { l }
+        e:spanned(<i:identifier() { ExprKind::Identifier(i) }>) { e }
+        "(" __ e:expression() __ ")" { e }
     }
 
+    /// The `load` and `store` intrinsics, which are named after the type of
+    /// value they access: `load8`, `load16`, `load32` and `load64` for
+    /// integers, `loadf32` and `loadf64` for floats, and `loadptr` for
+    /// pointers, and likewise for `store`.
+    rule memory_access() -> ExprKind
+        = "load" ty:access_type() _ "(" __ p:expression() __ ")"
+        { ExprKind::Load(ty, Box::new(p)) }
+        / "store" ty:access_type() _ "(" __ p:expression() __ "," __ v:expression() __ ")"
+        { ExprKind::Store(ty, Box::new(p), Box::new(v)) }
+
+    rule access_type() -> Ty
+        = t:(
+            "8" { Ty::I8 }
+            / "16" { Ty::I16 }
+            / "32" { Ty::I32 }
+            / "64" { Ty::I64 }
+            / "f32" { Ty::F32 }
+            / "f64" { Ty::F64 }
+            / "ptr" { Ty::Ptr }
+        ) !ident_char() { t }
+
+    /// The unsigned operators, which are written like calls: `udiv` and
+    /// `urem` for division and remainder, and `ult`, `ule`, `ugt` and `uge`
+    /// for the comparisons.
+    rule unsigned_op() -> ExprKind
+        = "udiv" o:operands() { ExprKind::UDiv(o.0, o.1) }
+        / "urem" o:operands() { ExprKind::URem(o.0, o.1) }
+        / "ult" o:operands() { ExprKind::ULt(o.0, o.1) }
+        / "ule" o:operands() { ExprKind::ULe(o.0, o.1) }
+        / "ugt" o:operands() { ExprKind::UGt(o.0, o.1) }
+        / "uge" o:operands() { ExprKind::UGe(o.0, o.1) }
+
+    rule operands() -> (Box<Expr>, Box<Expr>)
+        = _ "(" __ a:expression() __ "," __ b:expression() __ ")" { (Box::new(a), Box::new(b)) }
+
+    /// A comma-separated list, which may span several lines and may have a
+    /// trailing comma.
+    rule comma_list<T>(item: rule<T>) -> Vec<T>
+        = __ items:(item() ++ (__ "," __)) (__ ",")? __ { items }
+        / __ { Vec::new() }
+
+    rule ident_char() = ['a'..='z' | 'A'..='Z' | '0'..='9' | '_']
+
     rule identifier() -> String
         = quiet!{ n:$(['a'..='z' | 'A'..='Z' | '_']['a'..='z' | 'A'..='Z' | '0'..='9' | '_']*) { n.to_owned() } }
         / expected!("identifier")
 
     rule literal() -> Expr
-        = n:$(['0'..='9']+) { Expr::Literal(n.to_owned()) }
-        / "&" i:identifier() { Expr::GlobalDataAddr(i) }
+        = spanned(<n:float() { ExprKind::Float(n.to_owned()) }
+        / n:number() { ExprKind::Literal(n.to_owned()) }
+        / "'" c:character() "'" { ExprKind::Literal(u32::from(c).to_string()) }
+        / "\"" s:string_character()* "\"" { ExprKind::Str(s.into_iter().collect()) }
+        / "true" !ident_char() { ExprKind::Bool(true) }
+        / "false" !ident_char() { ExprKind::Bool(false) }
+        / "&" i:identifier() { ExprKind::GlobalDataAddr(i) }>)
+
+    /// The text of an integer literal, which may have a `0x`, `0o` or `0b`
+    /// radix prefix and may contain `_` separators between digits.
+    rule number() -> &'input str
+        = $("0x" ['0'..='9' | 'a'..='f' | 'A'..='F'] ['0'..='9' | 'a'..='f' | 'A'..='F' | '_']*)
+        / $("0o" ['0'..='7'] ['0'..='7' | '_']*)
+        / $("0b" ['0' | '1'] ['0' | '1' | '_']*)
+        / $(['0'..='9'] ['0'..='9' | '_']*)
+
+    /// The text of a floating-point literal, which must have digits after
+    /// the decimal point, an exponent, or both, so that `0..n` is a range
+    /// rather than `0.` followed by `.n`.
+    rule float() -> &'input str
+        = $(decimal() "." decimal() exponent()?)
+        / $(decimal() exponent())
+
+    rule decimal() = ['0'..='9'] ['0'..='9' | '_']*
+
+    rule exponent() = ['e' | 'E'] ['+' | '-']? ['0'..='9']+
+
+    rule character() -> char
+        = "\\" c:escape() { c }
+        / c:[^ '\'' | '\\' | '\n'] { c }
+
+    rule string_character() -> char
+        = "\\" c:escape() { c }
+        / c:[^ '"' | '\\' | '\n'] { c }
+
+    rule escape() -> char
+        = "n" { '\n' }
+        / "r" { '\r' }
+        / "t" { '\t' }
+        / "0" { '\0' }
+        / "\\" { '\\' }
+        / "'" { '\'' }
+        / "\"" { '"' }
+        / "x" n:$(['0'..='7'] ['0'..='9' | 'a'..='f' | 'A'..='F']) {
+            char::from(u8::from_str_radix(n, 16).unwrap())
+        }
+        / "u{" n:$(['0'..='9' | 'a'..='f' | 'A'..='F']*<1,6>) "}" {?
+            u32::from_str_radix(n, 16).ok().and_then(char::from_u32).ok_or("unicode escape")
+        }
+
+    /// Wrap the expression produced by `e` with the span of input it matched.
+    rule spanned(e: rule<ExprKind>) -> Expr
+        = start:position!() kind:e() end:position!() { Expr { kind, span: Span { start, end }, ty: None } }
+
+    /// Whitespace and comments within a line. A `\r` is treated as
+    /// whitespace so that CRLF line endings are accepted.
+    rule _() = quiet!{([' ' | '\t' | '\r'] / comment())*}
+
+    /// Whitespace and comments spanning any number of lines.
+    rule __() = quiet!{(_ "\n")* _}
+
+    rule comment()
+        = "//" [^ '\n']*
+        / block_comment()
 
-    rule _() =  quiet!{[' ' | '\t']*}
+    /// Block comments can be nested, so that code containing them can be
+    /// commented out.
+    rule block_comment()
+        = "/*" (block_comment() / !"*/" [_])* "*/"
 });
@@ -1,9 +1,16 @@
+use crate::call::{Args, Results};
+use crate::error::JitError;
 use crate::frontend::*;
-use cranelift::codegen::ir::BlockArg;
+use crate::layout::{self, StructLayout};
+use crate::runtime::{self, Runtime, Trap};
+use crate::typeck::{self, FunctionType, GlobalDef};
+use cranelift::codegen::ir::{self, BlockArg, Opcode, StackSlot};
+use cranelift::frontend::Switch;
 use cranelift::prelude::*;
 use cranelift_jit::{JITBuilder, JITModule};
-use cranelift_module::{DataDescription, Linkage, Module};
-use std::collections::HashMap;
+use cranelift_module::{DataDescription, DataId, FuncId, Linkage, Module};
+use std::collections::{HashMap, HashSet};
+use std::mem;
 use std::slice;
 
 /// The basic JIT class.
@@ -23,6 +30,61 @@ pub struct JIT {
     /// The module, with the jit backend, which manages the JIT'd
     /// functions.
     module: JITModule,
+
+    /// The state shared with the JIT'd code, such as the traps it has
+    /// raised. This is boxed so that its address, which the JIT'd code
+    /// refers to, doesn't change.
+    runtime: Box<Runtime>,
+
+    /// The types of all of the functions compiled so far, which calls to
+    /// them are checked against.
+    function_types: HashMap<String, FunctionType>,
+
+    /// The types of the host functions which have been called without being
+    /// declared with `extern fn`, which later calls are checked against.
+    imports: HashMap<String, FunctionType>,
+
+    /// The functions which have been defined and finalized, which are the
+    /// only ones `call` may call.
+    finalized: HashMap<String, FuncId>,
+
+    /// The trampolines used by `call`, keyed by the function they call.
+    trampolines: HashMap<FuncId, *const u8>,
+
+    /// Whether to check that loads and stores stay within data objects.
+    bounds_checks: bool,
+
+    /// The structs, external functions and globals declared so far.
+    scope: Scope,
+
+    /// The data objects which the JIT creates for string literals and
+    /// globals.
+    data_objects: DataObjects,
+}
+
+/// The structs, external functions and globals which functions can use,
+/// keyed by name.
+#[derive(Clone, Default)]
+struct Scope {
+    structs: HashMap<String, StructLayout>,
+    externs: HashMap<String, FunctionType>,
+    globals: HashMap<String, GlobalDef>,
+}
+
+#[derive(Default)]
+struct DataObjects {
+    /// The read-only data object holding each string literal, keyed by its
+    /// contents, so that each distinct string is only stored once.
+    strings: HashMap<String, DataId>,
+
+    /// The names of the data objects created by `create_data` or for global
+    /// variables, whose addresses functions can take.
+    defined: HashSet<String>,
+
+    /// The data objects which haven't been registered with the runtime yet,
+    /// because they haven't been finalized, along with the names to report
+    /// them by and whether they're writable.
+    unregistered: Vec<(String, DataId, bool)>,
 }
 
 impl Default for JIT {
@@ -30,13 +92,25 @@ impl Default for JIT {
         let mut flag_builder = settings::builder();
         flag_builder.set("use_colocated_libcalls", "false").unwrap();
         flag_builder.set("is_pic", "false").unwrap();
+        // Functions may return more values than fit in registers, in which
+        // case Cranelift returns the rest through memory.
+        flag_builder
+            .set("enable_multi_ret_implicit_sret", "true")
+            .unwrap();
         let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
             panic!("host machine is not supported: {}", msg);
         });
         let isa = isa_builder
             .finish(settings::Flags::new(flag_builder))
             .unwrap();
-        let builder = JITBuilder::with_isa(isa, cranelift_module::default_libcall_names());
+        let mut builder = JITBuilder::with_isa(isa, cranelift_module::default_libcall_names());
+
+        // Make the runtime support routines available to the JIT'd code.
+        builder.symbol(runtime::TRAP_SYMBOL, runtime::trap as *const u8);
+        builder.symbol(
+            runtime::CHECK_ACCESS_SYMBOL,
+            runtime::check_access as *const u8,
+        );
 
         let module = JITModule::new(builder);
         Self {
@@ -44,92 +118,531 @@ impl Default for JIT {
             ctx: module.make_context(),
             data_description: DataDescription::new(),
             module,
+            runtime: Box::default(),
+            function_types: HashMap::new(),
+            imports: HashMap::new(),
+            finalized: HashMap::new(),
+            trampolines: HashMap::new(),
+            bounds_checks: false,
+            scope: Scope::default(),
+            data_objects: DataObjects::default(),
         }
     }
 }
 
 impl JIT {
     /// Compile a string in the toy language into machine code.
-    pub fn compile(&mut self, input: &str) -> Result<*const u8, String> {
+    pub fn compile(&mut self, input: &str) -> Result<*const u8, JitError> {
         // First, parse the string, producing AST nodes.
-        let (name, params, the_return, stmts) =
-            parser::function(input).map_err(|e| e.to_string())?;
+        let function = parser::function(input)?;
+        let name = function.name.clone();
+
+        let mut code = self.compile_functions(vec![function], self.scope.clone(), Vec::new())?;
+        Ok(code.remove(&name).unwrap())
+    }
+
+    /// Compile a string containing any number of structs, external
+    /// functions, globals and functions in the toy language into machine
+    /// code. The functions may call each other in any order, including
+    /// mutually recursively. Returns a map from each function's name to its
+    /// machine code.
+    pub fn compile_program(&mut self, input: &str) -> Result<HashMap<String, *const u8>, JitError> {
+        let Program {
+            structs,
+            externs,
+            globals,
+            functions,
+        } = parser::program(input)?;
+
+        // The structs, external functions and globals are only added to
+        // those the JIT knows about once the whole program has compiled
+        // successfully.
+        let pointer_bytes = self.module.target_config().pointer_bytes().into();
+        let mut scope = self.scope.clone();
+        for decl in &structs {
+            if scope.structs.contains_key(&decl.name) {
+                return Err(JitError::Redefinition {
+                    name: decl.name.clone(),
+                    span: decl.span,
+                });
+            }
+            let layout = layout::layout_struct(decl, pointer_bytes)?;
+            scope.structs.insert(decl.name.clone(), layout);
+        }
 
-        // Then, translate the AST nodes into Cranelift IR.
-        self.translate(params, the_return, stmts)?;
+        // An external function is imported from the host, so it has to
+        // exist there, and can't share its name with anything the JIT
+        // defines.
+        for decl in &externs {
+            if runtime::is_reserved(&decl.name) {
+                return Err(JitError::ReservedName {
+                    name: decl.name.clone(),
+                    span: decl.span,
+                });
+            }
+            let ty = FunctionType::of_extern(decl);
+            let clashes = scope.externs.contains_key(&decl.name)
+                || self.function_types.contains_key(&decl.name)
+                || self.data_objects.defined.contains(&decl.name)
+                || self
+                    .imports
+                    .get(&decl.name)
+                    .is_some_and(|import| *import != ty);
+            if clashes {
+                return Err(JitError::Redefinition {
+                    name: decl.name.clone(),
+                    span: decl.span,
+                });
+            }
+            if !host_has_symbol(&decl.name) {
+                return Err(JitError::UndefinedFunction {
+                    name: decl.name.clone(),
+                    span: Some(decl.span),
+                });
+            }
+            scope.externs.insert(decl.name.clone(), ty);
+        }
 
-        // Next, declare the function to jit. Functions must be declared
-        // before they can be called, or defined.
+        // Constants only exist at compile time, but global variables need
+        // data objects to hold them.
+        let mut global_data = Vec::new();
+        for global in globals {
+            // A global variable's data object can't share its name with any
+            // other data object or function.
+            let clashes = scope.globals.contains_key(&global.name)
+                || !global.constant
+                    && (self.data_objects.defined.contains(&global.name)
+                        || self.function_types.contains_key(&global.name)
+                        || self.imports.contains_key(&global.name)
+                        || scope.externs.contains_key(&global.name));
+            if clashes {
+                return Err(JitError::Redefinition {
+                    name: global.name,
+                    span: global.span,
+                });
+            }
+            let ty = match global.ty {
+                Some(ty) => ty,
+                None => constant_type(&global.value)?,
+            };
+            let contents = constant_bytes(&global.value, ty, pointer_bytes)?;
+            let constant = if global.constant {
+                Some(contents)
+            } else {
+                global_data.push((global.name.clone(), contents));
+                None
+            };
+            scope
+                .globals
+                .insert(global.name, GlobalDef { ty, constant });
+        }
+        self.compile_functions(functions, scope, global_data)
+    }
+
+    // Compile `functions`, which may use the structs and globals in `scope`,
+    // and define the data objects for the new global variables in
+    // `global_data`, which holds each one's name and initial contents. Once
+    // they've been compiled, `scope` replaces the JIT's own.
+    fn compile_functions(
+        &mut self,
+        mut functions: Vec<Function>,
+        scope: Scope,
+        global_data: Vec<(String, Vec<u8>)>,
+    ) -> Result<HashMap<String, *const u8>, JitError> {
+        // A function can only be defined once, and can't share its name with
+        // an external function or a data object, so check for clashes before
+        // declaring anything, which couldn't be undone.
+        let mut seen = HashSet::new();
+        for function in &functions {
+            let name = &function.name;
+            if runtime::is_reserved(name) {
+                return Err(JitError::ReservedName {
+                    name: name.clone(),
+                    span: function.span,
+                });
+            }
+            let clashes = !seen.insert(name)
+                || self.function_types.contains_key(name)
+                || scope.externs.contains_key(name)
+                || self.imports.contains_key(name)
+                || self.data_objects.defined.contains(name)
+                || global_data.iter().any(|(global, _)| global == name);
+            if clashes {
+                return Err(JitError::Redefinition {
+                    name: function.name.clone(),
+                    span: function.span,
+                });
+            }
+        }
+
+        // Type check all of the functions before translating any of them.
+        // They're checked against the types of the functions compiled so
+        // far, the external functions and those in the unit, but the JIT
+        // only learns the types of the new functions once they've all
+        // compiled successfully. This happens before anything is declared,
+        // and type checking catches every error in the source, so a unit
+        // which fails to compile leaves nothing behind in the module.
+        let pointer_bytes = self.module.target_config().pointer_bytes().into();
+        let mut function_types = self.function_types.clone();
+        function_types.extend(self.imports.clone());
+        function_types.extend(scope.externs.clone());
+        let new_types: Vec<_> = functions.iter().map(FunctionType::of).collect();
+        for (function, ty) in functions.iter().zip(&new_types) {
+            function_types.insert(function.name.clone(), ty.clone());
+        }
+        let mut imports = Vec::new();
+        let mut variables = Vec::new();
+        for function in &mut functions {
+            variables.push(typeck::check_function(
+                function,
+                &function_types,
+                &mut imports,
+                &scope.structs,
+                &scope.globals,
+                &self.data_objects.defined,
+                pointer_bytes,
+            )?);
+        }
+
+        // The module looks up the functions which the JIT doesn't define in
+        // the host process when they're finalized, and panics if one of them
+        // is missing, so check that they exist first. A data object isn't a
+        // function, even though the host may have a symbol of that name.
+        for import in &imports {
+            let missing = self.data_objects.defined.contains(&import.name)
+                || global_data.iter().any(|(global, _)| *global == import.name)
+                || !host_has_symbol(&import.name);
+            if missing {
+                return Err(JitError::UndefinedFunction {
+                    name: import.name.clone(),
+                    span: Some(import.span),
+                });
+            }
+            function_types.insert(import.name.clone(), import.ty.clone());
+        }
+
+        // Declare all of the functions to jit up front. Functions must be
+        // declared before they can be called, or defined, so doing this first
+        // lets the functions in a unit refer to each other regardless of the
+        // order in which they appear.
         //
         // TODO: This may be an area where the API should be streamlined; should
         // we have a version of `declare_function` that automatically declares
         // the function?
-        let id = self
-            .module
-            .declare_function(&name, Linkage::Export, &self.ctx.func.signature)
-            .map_err(|e| e.to_string())?;
-
-        // Define the function to jit. This finishes compilation, although
-        // there may be outstanding relocations to perform. Currently, jit
-        // cannot finish relocations until all functions to be called are
-        // defined. For this toy demo for now, we'll just finalize the
-        // function below.
-        self.module
-            .define_function(id, &mut self.ctx)
-            .map_err(|e| e.to_string())?;
-
-        // Now that compilation is finished, we can clear out the context state.
-        self.module.clear_context(&mut self.ctx);
+        let mut ids = Vec::new();
+        for (function, ty) in functions.iter().zip(&new_types) {
+            let sig = make_signature(&self.module, ty);
+            let id = self
+                .module
+                .declare_function(&function.name, Linkage::Export, &sig)?;
+            ids.push(id);
+        }
+
+        // Translate the AST nodes into Cranelift IR. Since type checking has
+        // caught any errors in the source, this can only fail if there's a
+        // bug in the translation. All of the functions are translated before
+        // any of them are defined, so that a failure in one of them doesn't
+        // leave the others defined.
+        let mut names = Vec::new();
+        let mut funcs = Vec::new();
+        for (function, variables) in functions.into_iter().zip(variables) {
+            names.push(function.name.clone());
+            let result = self.translate(function, variables, &scope, &function_types);
+            if let Err(err) = result {
+                // Discard the partially-built function so that the JIT can be
+                // used for the next compile.
+                self.builder_context = FunctionBuilderContext::new();
+                self.module.clear_context(&mut self.ctx);
+                return Err(err);
+            }
+            funcs.push(mem::replace(&mut self.ctx.func, ir::Function::new()));
+        }
+
+        // Define the data objects for the new globals before defining any of
+        // the functions, so that a problem with one of them doesn't leave the
+        // functions defined.
+        for (name, contents) in global_data {
+            let id = self
+                .module
+                .declare_data(&name, Linkage::Export, true, false)?;
+            let mut description = DataDescription::new();
+            description.set_align(contents.len() as u64);
+            description.define(contents.into_boxed_slice());
+            self.module.define_data(id, &description)?;
+            self.data_objects.defined.insert(name.clone());
+            self.data_objects.unregistered.push((name, id, true));
+        }
+
+        for (func, &id) in funcs.into_iter().zip(&ids) {
+            self.ctx.func = func;
+
+            // Define the function to jit. This finishes compilation, although
+            // there may be outstanding relocations to perform. Currently, jit
+            // cannot finish relocations until all functions to be called are
+            // defined, so we'll finalize them all together below.
+            let result = self.module.define_function(id, &mut self.ctx);
+
+            // Now that compilation is finished, we can clear out the context state.
+            self.module.clear_context(&mut self.ctx);
+            result?;
+        }
 
         // Finalize the functions which we just defined, which resolves any
         // outstanding relocations (patching in addresses, now that they're
         // available).
-        self.module.finalize_definitions().unwrap();
+        self.module.finalize_definitions()?;
+
+        // Now that the string literals and globals the functions use have
+        // addresses, tell the runtime where they are.
+        for (name, id, writable) in self.data_objects.unregistered.drain(..) {
+            let (start, len) = self.module.get_finalized_data(id);
+            self.runtime.register_data(&name, start, len, writable);
+        }
+
+        // We can now retrieve pointers to the machine code.
+        let code = names
+            .iter()
+            .zip(&ids)
+            .map(|(name, &id)| (name.clone(), self.module.get_finalized_function(id)))
+            .collect();
 
-        // We can now retrieve a pointer to the machine code.
-        let code = self.module.get_finalized_function(id);
+        self.finalized.extend(names.iter().cloned().zip(ids));
+        self.function_types.extend(names.into_iter().zip(new_types));
+        self.imports
+            .extend(imports.into_iter().map(|import| (import.name, import.ty)));
 
+        self.scope = scope;
         Ok(code)
     }
 
+    /// Call the JIT'd function `name`, passing it `args` and returning its
+    /// results, both as tuples. Unlike calling a pointer returned by
+    /// `compile`, this checks the arguments and results against the
+    /// function's signature, works for functions returning any number of
+    /// values, and reports any trap raised by the call as an error.
+    ///
+    /// ```ignore
+    /// let (q, r): (i64, i64) = unsafe { jit.call("divmod", (17, 5))? };
+    /// ```
+    ///
+    /// # Safety
+    ///
+    /// The function runs with the same access to memory as the host, so the
+    /// caller has to make sure that any pointers it's passed, or that it
+    /// loads or stores through, or hands to host functions such as `free`,
+    /// are valid for what it does with them. Checking the signature doesn't
+    /// make the call safe, only well-typed.
+    pub unsafe fn call<A: Args, R: Results>(&mut self, name: &str, args: A) -> Result<R, JitError> {
+        let (Some(expected), Some(&id)) = (self.function_types.get(name), self.finalized.get(name))
+        else {
+            return Err(JitError::UndefinedFunction {
+                name: name.to_owned(),
+                span: None,
+            });
+        };
+        let found = FunctionType {
+            params: A::TYPES.to_vec(),
+            returns: R::TYPES.to_vec(),
+        };
+        // A struct reference is passed as a plain pointer.
+        let compatible = |expected: &[Ty], found: &[Ty]| {
+            expected.len() == found.len()
+                && expected
+                    .iter()
+                    .zip(found)
+                    .all(|(&a, &b)| a == b || a.is_pointer() && b.is_pointer())
+        };
+        if !compatible(&expected.params, &found.params)
+            || !compatible(&expected.returns, &found.returns)
+        {
+            return Err(JitError::SignatureMismatch {
+                name: name.to_owned(),
+                expected: expected.to_string(),
+                found: found.to_string(),
+            });
+        }
+
+        let trampoline = self.trampoline(id)?;
+        // Safety: the trampoline was generated with exactly this signature.
+        let trampoline =
+            unsafe { mem::transmute::<*const u8, extern "C" fn(*const u64, *mut u64)>(trampoline) };
+
+        // Discard any trap left over from calling a compiled function
+        // directly, so that it isn't blamed on this call.
+        self.take_trap();
+
+        let args = args.to_slots();
+        let mut results = vec![0; R::TYPES.len()];
+        trampoline(args.as_ptr(), results.as_mut_ptr());
+
+        match self.take_trap() {
+            Some(trap) => Err(JitError::Trap(trap)),
+            None => Ok(R::from_slots(&results)),
+        }
+    }
+
+    /// Enable or disable bounds checks in the code compiled from now on.
+    /// When they're enabled, every load and store checks that it lies
+    /// entirely within a data object created by `create_data`. One which
+    /// doesn't reports a trap identifying the data object and the offset
+    /// into it, and the function returns instead of making the access.
+    ///
+    /// This makes memory accesses much slower, but means that a script with
+    /// a bad index can't corrupt the host process.
+    pub fn set_bounds_checks(&mut self, enabled: bool) {
+        self.bounds_checks = enabled;
+    }
+
+    /// Return the first trap raised by JIT'd code since the last call to
+    /// this, if any. Call this after running JIT'd code to find out whether
+    /// it hit a runtime error. Until the trap is taken, JIT'd functions
+    /// return as soon as a call they make returns, as though it had trapped.
+    pub fn take_trap(&mut self) -> Option<Trap> {
+        self.runtime.take_trap()
+    }
+
     /// Create a zero-initialized data section.
-    pub fn create_data(&mut self, name: &str, contents: Vec<u8>) -> Result<&[u8], String> {
+    pub fn create_data(&mut self, name: &str, contents: Vec<u8>) -> Result<&[u8], JitError> {
         // The steps here are analogous to `compile`, except that data is much
         // simpler than functions.
         self.data_description.define(contents.into_boxed_slice());
         let id = self
             .module
-            .declare_data(name, Linkage::Export, true, false)
-            .map_err(|e| e.to_string())?;
+            .declare_data(name, Linkage::Export, true, false)?;
 
-        self.module
-            .define_data(id, &self.data_description)
-            .map_err(|e| e.to_string())?;
+        self.module.define_data(id, &self.data_description)?;
         self.data_description.clear();
-        self.module.finalize_definitions().unwrap();
+        self.module.finalize_definitions()?;
         let buffer = self.module.get_finalized_data(id);
+        self.runtime.register_data(name, buffer.0, buffer.1, true);
+        self.data_objects.defined.insert(name.to_owned());
         // TODO: Can we move the unsafe into cranelift?
         Ok(unsafe { slice::from_raw_parts(buffer.0, buffer.1) })
     }
 
-    // Translate from toy-language AST nodes into Cranelift IR.
-    fn translate(
-        &mut self,
-        params: Vec<String>,
-        the_return: String,
-        stmts: Vec<Expr>,
-    ) -> Result<(), String> {
-        // Our toy language currently only supports I64 values, though Cranelift
-        // supports other types.
-        let int = self.module.target_config().pointer_type();
+    /// Create a data object holding a struct, whose fields are initialized
+    /// from a struct literal, such as `Point { x: 1, y: 2 }`. The struct must
+    /// have been declared by a program compiled earlier. Fields which the
+    /// literal leaves out are zeroed.
+    pub fn create_struct_data(&mut self, name: &str, literal: &str) -> Result<&[u8], JitError> {
+        let literal = parser::struct_literal(literal)?;
+        let Some(layout) = self.scope.structs.get(&literal.name) else {
+            return Err(JitError::UndefinedStruct {
+                name: literal.name,
+                span: literal.span,
+            });
+        };
+
+        let pointer_bytes = self.module.target_config().pointer_bytes().into();
+        let mut contents = vec![0; layout.size as usize];
+        let mut initialized = Vec::new();
+        for (field, value) in &literal.fields {
+            let Some(field_layout) = layout.field(field) else {
+                return Err(JitError::UndefinedField {
+                    name: literal.name,
+                    field: field.clone(),
+                    span: value.span,
+                });
+            };
+            if initialized.contains(&field) {
+                return Err(JitError::Redefinition {
+                    name: field.clone(),
+                    span: value.span,
+                });
+            }
+            initialized.push(field);
+
+            let bytes = constant_bytes(value, field_layout.ty, pointer_bytes)?;
+            let offset = field_layout.offset as usize;
+            contents[offset..offset + bytes.len()].copy_from_slice(&bytes);
+        }
+        self.create_data(name, contents)
+    }
+
+    // Get a trampoline for calling the function `id` from Rust, compiling
+    // it if there isn't one already. The trampoline takes a pointer to an
+    // array of arguments and a pointer to an array to write the results to,
+    // with one 64-bit slot per value, loads the arguments, calls the
+    // function, and stores its results. This means the Rust side of a call
+    // doesn't need to know how the calling convention passes each value.
+    fn trampoline(&mut self, id: FuncId) -> Result<*const u8, JitError> {
+        if let Some(&code) = self.trampolines.get(&id) {
+            return Ok(code);
+        }
+
+        let callee_signature = self
+            .module
+            .declarations()
+            .get_function_decl(id)
+            .signature
+            .clone();
+        let pointer = self.module.target_config().pointer_type();
+        self.ctx.func.signature.params.push(AbiParam::new(pointer));
+        self.ctx.func.signature.params.push(AbiParam::new(pointer));
+
+        let mut builder = FunctionBuilder::new(&mut self.ctx.func, &mut self.builder_context);
+        let entry_block = builder.create_block();
+        builder.append_block_params_for_function_params(entry_block);
+        builder.switch_to_block(entry_block);
+        builder.seal_block(entry_block);
+        let args_pointer = builder.block_params(entry_block)[0];
+        let results_pointer = builder.block_params(entry_block)[1];
+
+        let mut args = Vec::new();
+        for (i, param) in callee_signature.params.iter().enumerate() {
+            let offset = (i * 8) as i32;
+            let arg =
+                builder
+                    .ins()
+                    .load(param.value_type, MemFlags::trusted(), args_pointer, offset);
+            args.push(arg);
+        }
 
-        for _p in &params {
-            self.ctx.func.signature.params.push(AbiParam::new(int));
+        let callee = self.module.declare_func_in_func(id, builder.func);
+        let call = builder.ins().call(callee, &args);
+        let results = builder.inst_results(call).to_vec();
+
+        for (i, result) in results.into_iter().enumerate() {
+            let offset = (i * 8) as i32;
+            builder
+                .ins()
+                .store(MemFlags::trusted(), result, results_pointer, offset);
         }
+        builder.ins().return_(&[]);
+        builder.finalize();
+
+        let trampoline_id = self
+            .module
+            .declare_anonymous_function(&self.ctx.func.signature)?;
+        let result = self.module.define_function(trampoline_id, &mut self.ctx);
+        self.module.clear_context(&mut self.ctx);
+        result?;
+        self.module.finalize_definitions()?;
 
-        // Our toy language currently only supports one return value, though
-        // Cranelift is designed to support more.
-        self.ctx.func.signature.returns.push(AbiParam::new(int));
+        let code = self.module.get_finalized_function(trampoline_id);
+        self.trampolines.insert(id, code);
+        Ok(code)
+    }
+
+    // Translate from toy-language AST nodes into Cranelift IR. `variables`
+    // holds the names and types of the function's variables, as found by
+    // type checking, `scope` the structs and globals it may use, and
+    // `functions` the types of all of the functions it may call.
+    fn translate(
+        &mut self,
+        function: Function,
+        variables: Vec<(String, Ty)>,
+        scope: &Scope,
+        functions: &HashMap<String, FunctionType>,
+    ) -> Result<(), JitError> {
+        let int = self.module.target_config().pointer_type();
+        self.ctx.func.signature = make_signature(&self.module, &FunctionType::of(&function));
+        let Function {
+            params,
+            returns,
+            stmts,
+            ..
+        } = function;
 
         // Create the builder to build a function.
         let mut builder = FunctionBuilder::new(&mut self.ctx.func, &mut self.builder_context);
@@ -151,30 +664,48 @@ impl JIT {
         // predecessors.
         builder.seal_block(entry_block);
 
-        // The toy language allows variables to be declared implicitly.
-        // Walk the AST and declare all implicitly-declared variables.
-        let variables =
-            declare_variables(int, &mut builder, &params, &the_return, &stmts, entry_block);
+        // The toy language allows variables to be declared implicitly, so
+        // declare all of the variables which type checking found.
+        let variables = declare_variables(
+            int,
+            &mut builder,
+            &params,
+            &returns,
+            &variables,
+            entry_block,
+        );
 
         // Now translate the statements of the function body.
+        let return_variables = returns.iter().map(|(name, _)| variables[name]).collect();
         let mut trans = FunctionTranslator {
             int,
             builder,
             variables,
+            return_variables,
             module: &mut self.module,
+            runtime: &*self.runtime,
+            bounds_checks: self.bounds_checks,
+            loops: Vec::new(),
+            arrays: HashMap::new(),
+            structs: &scope.structs,
+            globals: &scope.globals,
+            functions,
+            data_objects: &mut self.data_objects,
         };
         for expr in stmts {
-            trans.translate_expr(expr);
+            trans.translate_expr(expr)?;
         }
 
-        // Set up the return variable of the function. Above, we declared a
-        // variable to hold the return value. Here, we just do a use of that
-        // variable.
-        let return_variable = trans.variables.get(&the_return).unwrap();
-        let return_value = trans.builder.use_var(*return_variable);
+        // Set up the return variables of the function. Above, we declared
+        // variables to hold the return values. Here, we just do a use of
+        // those variables.
+        //
+        // If the body ended with a `return`, we're now in a block which can't
+        // be reached, but it still needs a terminator like any other.
+        let return_values = trans.use_return_variables();
 
         // Emit the return instruction.
-        trans.builder.ins().return_(&[return_value]);
+        trans.builder.ins().return_(&return_values);
 
         // Tell the builder we're done with this function.
         trans.builder.finalize();
@@ -188,129 +719,849 @@ struct FunctionTranslator<'a> {
     int: types::Type,
     builder: FunctionBuilder<'a>,
     variables: HashMap<String, Variable>,
+    return_variables: Vec<Variable>,
     module: &'a mut JITModule,
+    runtime: *const Runtime,
+
+    /// The loops enclosing the code being translated, innermost last.
+    loops: Vec<Loop>,
+
+    /// Whether to check the addresses of loads and stores.
+    bounds_checks: bool,
+
+    /// The local arrays declared so far.
+    arrays: HashMap<String, Array>,
+
+    structs: &'a HashMap<String, StructLayout>,
+    globals: &'a HashMap<String, GlobalDef>,
+    functions: &'a HashMap<String, FunctionType>,
+    data_objects: &'a mut DataObjects,
+}
+
+/// A local array, which lives in a stack slot.
+#[derive(Clone, Copy)]
+struct Array {
+    slot: StackSlot,
+    ty: types::Type,
+    len: u32,
+}
+
+/// The blocks which `break` and `continue` jump to within a loop.
+struct Loop {
+    label: Option<String>,
+    continue_block: Block,
+    break_block: Block,
 }
 
 impl<'a> FunctionTranslator<'a> {
     /// When you write out instructions in Cranelift, you get back `Value`s. You
     /// can then use these references in other instructions.
-    fn translate_expr(&mut self, expr: Expr) -> Value {
-        match expr {
-            Expr::Literal(literal) => {
-                let imm: i32 = literal.parse().unwrap();
-                self.builder.ins().iconst(self.int, i64::from(imm))
+    fn translate_expr(&mut self, expr: Expr) -> Result<Value, JitError> {
+        let Expr { kind, span, ty } = expr;
+        let value = match kind {
+            ExprKind::Literal(literal) => self.translate_literal(literal, false, ty),
+
+            ExprKind::Float(literal) => self.translate_float(literal, ty),
+
+            ExprKind::Bool(value) => self.builder.ins().iconst(types::I8, i64::from(value)),
+
+            ExprKind::Str(string) => self.translate_string(string)?,
+
+            ExprKind::Neg(operand) => match operand.kind {
+                // Negative literals are folded here, rather than negating the
+                // positive value, so that `i64::MIN` can be written directly.
+                ExprKind::Literal(literal) => self.translate_literal(literal, true, ty),
+                _ => {
+                    let operand = self.translate_expr(*operand)?;
+                    if is_float(ty) {
+                        self.builder.ins().fneg(operand)
+                    } else {
+                        self.builder.ins().ineg(operand)
+                    }
+                }
+            },
+
+            ExprKind::Not(operand) => {
+                let operand = self.translate_expr(*operand)?;
+                self.builder.ins().icmp_imm(IntCC::Equal, operand, 0)
+            }
+
+            ExprKind::Cast(operand, to) => {
+                let from = operand.ty.unwrap();
+                let operand = self.translate_expr(*operand)?;
+                self.translate_cast(operand, from, to)
+            }
+
+            ExprKind::And(lhs, rhs) => self.translate_short_circuit(true, *lhs, *rhs)?,
+            ExprKind::Or(lhs, rhs) => self.translate_short_circuit(false, *lhs, *rhs)?,
+
+            ExprKind::Add(lhs, rhs) => {
+                let lhs = self.translate_expr(*lhs)?;
+                let rhs = self.translate_expr(*rhs)?;
+                if is_float(ty) {
+                    self.builder.ins().fadd(lhs, rhs)
+                } else {
+                    self.builder.ins().iadd(lhs, rhs)
+                }
+            }
+
+            ExprKind::Sub(lhs, rhs) => {
+                let lhs = self.translate_expr(*lhs)?;
+                let rhs = self.translate_expr(*rhs)?;
+                if is_float(ty) {
+                    self.builder.ins().fsub(lhs, rhs)
+                } else {
+                    self.builder.ins().isub(lhs, rhs)
+                }
+            }
+
+            ExprKind::Mul(lhs, rhs) => {
+                let lhs = self.translate_expr(*lhs)?;
+                let rhs = self.translate_expr(*rhs)?;
+                if is_float(ty) {
+                    self.builder.ins().fmul(lhs, rhs)
+                } else {
+                    self.builder.ins().imul(lhs, rhs)
+                }
+            }
+
+            // Dividing floats can't trap; dividing by zero produces an
+            // infinity or NaN.
+            ExprKind::Div(lhs, rhs) if is_float(ty) => {
+                let lhs = self.translate_expr(*lhs)?;
+                let rhs = self.translate_expr(*rhs)?;
+                self.builder.ins().fdiv(lhs, rhs)
+            }
+            ExprKind::Div(lhs, rhs) => self.translate_division(Opcode::Sdiv, *lhs, *rhs)?,
+            ExprKind::Rem(lhs, rhs) => self.translate_division(Opcode::Srem, *lhs, *rhs)?,
+            ExprKind::UDiv(lhs, rhs) => self.translate_division(Opcode::Udiv, *lhs, *rhs)?,
+            ExprKind::URem(lhs, rhs) => self.translate_division(Opcode::Urem, *lhs, *rhs)?,
+
+            ExprKind::BitAnd(lhs, rhs) => {
+                let lhs = self.translate_expr(*lhs)?;
+                let rhs = self.translate_expr(*rhs)?;
+                self.builder.ins().band(lhs, rhs)
             }
 
-            Expr::Add(lhs, rhs) => {
-                let lhs = self.translate_expr(*lhs);
-                let rhs = self.translate_expr(*rhs);
-                self.builder.ins().iadd(lhs, rhs)
+            ExprKind::BitOr(lhs, rhs) => {
+                let lhs = self.translate_expr(*lhs)?;
+                let rhs = self.translate_expr(*rhs)?;
+                self.builder.ins().bor(lhs, rhs)
             }
 
-            Expr::Sub(lhs, rhs) => {
-                let lhs = self.translate_expr(*lhs);
-                let rhs = self.translate_expr(*rhs);
-                self.builder.ins().isub(lhs, rhs)
+            ExprKind::BitXor(lhs, rhs) => {
+                let lhs = self.translate_expr(*lhs)?;
+                let rhs = self.translate_expr(*rhs)?;
+                self.builder.ins().bxor(lhs, rhs)
             }
 
-            Expr::Mul(lhs, rhs) => {
-                let lhs = self.translate_expr(*lhs);
-                let rhs = self.translate_expr(*rhs);
-                self.builder.ins().imul(lhs, rhs)
+            ExprKind::Shl(lhs, rhs) => {
+                let lhs = self.translate_expr(*lhs)?;
+                let rhs = self.translate_expr(*rhs)?;
+                self.builder.ins().ishl(lhs, rhs)
             }
 
-            Expr::Div(lhs, rhs) => {
-                let lhs = self.translate_expr(*lhs);
-                let rhs = self.translate_expr(*rhs);
-                self.builder.ins().udiv(lhs, rhs)
+            ExprKind::Shr(lhs, rhs) => {
+                let lhs = self.translate_expr(*lhs)?;
+                let rhs = self.translate_expr(*rhs)?;
+                self.builder.ins().sshr(lhs, rhs)
             }
 
-            Expr::Eq(lhs, rhs) => self.translate_icmp(IntCC::Equal, *lhs, *rhs),
-            Expr::Ne(lhs, rhs) => self.translate_icmp(IntCC::NotEqual, *lhs, *rhs),
-            Expr::Lt(lhs, rhs) => self.translate_icmp(IntCC::SignedLessThan, *lhs, *rhs),
-            Expr::Le(lhs, rhs) => self.translate_icmp(IntCC::SignedLessThanOrEqual, *lhs, *rhs),
-            Expr::Gt(lhs, rhs) => self.translate_icmp(IntCC::SignedGreaterThan, *lhs, *rhs),
-            Expr::Ge(lhs, rhs) => self.translate_icmp(IntCC::SignedGreaterThanOrEqual, *lhs, *rhs),
-            Expr::Call(name, args) => self.translate_call(name, args),
-            Expr::GlobalDataAddr(name) => self.translate_global_data_addr(name),
-            Expr::Identifier(name) => {
+            ExprKind::UShr(lhs, rhs) => {
+                let lhs = self.translate_expr(*lhs)?;
+                let rhs = self.translate_expr(*rhs)?;
+                self.builder.ins().ushr(lhs, rhs)
+            }
+
+            ExprKind::BitNot(operand) => {
+                let operand = self.translate_expr(*operand)?;
+                self.builder.ins().bnot(operand)
+            }
+
+            ExprKind::Eq(lhs, rhs) => self.translate_comparison(IntCC::Equal, *lhs, *rhs)?,
+            ExprKind::Ne(lhs, rhs) => self.translate_comparison(IntCC::NotEqual, *lhs, *rhs)?,
+            ExprKind::Lt(lhs, rhs) => {
+                self.translate_comparison(IntCC::SignedLessThan, *lhs, *rhs)?
+            }
+            ExprKind::Le(lhs, rhs) => {
+                self.translate_comparison(IntCC::SignedLessThanOrEqual, *lhs, *rhs)?
+            }
+            ExprKind::Gt(lhs, rhs) => {
+                self.translate_comparison(IntCC::SignedGreaterThan, *lhs, *rhs)?
+            }
+            ExprKind::Ge(lhs, rhs) => {
+                self.translate_comparison(IntCC::SignedGreaterThanOrEqual, *lhs, *rhs)?
+            }
+            ExprKind::ULt(lhs, rhs) => {
+                self.translate_comparison(IntCC::UnsignedLessThan, *lhs, *rhs)?
+            }
+            ExprKind::ULe(lhs, rhs) => {
+                self.translate_comparison(IntCC::UnsignedLessThanOrEqual, *lhs, *rhs)?
+            }
+            ExprKind::UGt(lhs, rhs) => {
+                self.translate_comparison(IntCC::UnsignedGreaterThan, *lhs, *rhs)?
+            }
+            ExprKind::UGe(lhs, rhs) => {
+                self.translate_comparison(IntCC::UnsignedGreaterThanOrEqual, *lhs, *rhs)?
+            }
+            ExprKind::Call(name, args) => {
+                // When a call is used as a value, its value is the first
+                // value returned by the callee.
+                let results = self.translate_call(name, args)?;
+                match results.first() {
+                    Some(&result) => result,
+                    None => self.unit_value(),
+                }
+            }
+            ExprKind::GlobalDataAddr(name) => self.translate_global_data_addr(name)?,
+            ExprKind::Field(object, field) => self.translate_field(*object, field, None)?,
+            ExprKind::FieldAssign(object, field, value) => {
+                self.translate_field(*object, field, Some(*value))?
+            }
+            ExprKind::Load(ty, address) => {
+                let address = self.translate_expr(*address)?;
+                let ty = cranelift_type(ty, self.int);
+                let address = self.translate_address(address, ty, false)?;
+                self.builder.ins().load(ty, memory_flags(), address, 0)
+            }
+            ExprKind::Store(_, address, value) => {
+                let address = self.translate_expr(*address)?;
+                let value = self.translate_expr(*value)?;
+                let ty = self.builder.func.dfg.value_type(value);
+                let address = self.translate_address(address, ty, true)?;
+                self.builder.ins().store(memory_flags(), value, address, 0);
+
+                self.unit_value()
+            }
+            ExprKind::Identifier(name) => match self.variables.get(&name) {
                 // `use_var` is used to read the value of a variable.
-                let variable = self.variables.get(&name).expect("variable not defined");
-                self.builder.use_var(*variable)
+                Some(&variable) => self.builder.use_var(variable),
+                None => self.translate_global(name, span)?,
+            },
+            ExprKind::Assign(name, _, expr) => self.translate_assign(name, *expr, span)?,
+            ExprKind::TupleAssign(names, expr) => {
+                self.translate_tuple_assign(names, *expr, span)?
+            }
+            ExprKind::ArrayDecl(name, ty, len) => self.translate_array_decl(name, ty, len),
+            ExprKind::Index(name, index) => self.translate_index(name, *index, None)?,
+            ExprKind::IndexAssign(name, index, value) => {
+                self.translate_index(name, *index, Some(*value))?
+            }
+            ExprKind::IfElse(branches, else_body) => {
+                self.translate_if_else(branches, else_body, ty)?
             }
-            Expr::Assign(name, expr) => self.translate_assign(name, *expr),
-            Expr::IfElse(condition, then_body, else_body) => {
-                self.translate_if_else(*condition, then_body, else_body)
+            ExprKind::Match(scrutinee, arms) => self.translate_match(*scrutinee, arms, ty)?,
+            ExprKind::WhileLoop(label, condition, loop_body) => {
+                self.translate_while_loop(label, *condition, loop_body)?
             }
-            Expr::WhileLoop(condition, loop_body) => {
-                self.translate_while_loop(*condition, loop_body)
+            ExprKind::ForLoop {
+                label,
+                var,
+                start,
+                end,
+                inclusive,
+                step,
+                body,
+            } => self.translate_for_loop(label, var, *start, *end, inclusive, step, body, span)?,
+            ExprKind::Break(label) => {
+                let target = self.find_loop(label).break_block;
+                self.translate_jump_out(target)
             }
+            ExprKind::Continue(label) => {
+                let target = self.find_loop(label).continue_block;
+                self.translate_jump_out(target)
+            }
+            ExprKind::Return(values) => self.translate_return(values)?,
+        };
+        Ok(value)
+    }
+
+    fn translate_literal(&mut self, literal: String, negate: bool, ty: Option<Ty>) -> Value {
+        // Type checking has made sure that the literal fits in its type.
+        let ty = cranelift_type(ty.unwrap(), self.int);
+        let imm = typeck::parse_literal(&literal, negate, ty.bits()).unwrap();
+        self.iconst(ty, imm)
+    }
+
+    /// Produce the integer constant `imm`, truncated to the width of `ty`.
+    /// Cranelift expects the immediates of types narrower than 64 bits to be
+    /// zero-extended, so a negative value has to have its upper bits cleared.
+    fn iconst(&mut self, ty: types::Type, imm: i64) -> Value {
+        let imm = match ty.bits() {
+            64 => imm,
+            bits => imm & ((1 << bits) - 1),
+        };
+        self.builder.ins().iconst(ty, imm)
+    }
+
+    /// Convert `value`, of type `from`, to type `to`. Integers are
+    /// sign-extended or truncated, while booleans and pointers are
+    /// zero-extended. Converting to `bool` tests whether the value is
+    /// non-zero.
+    fn translate_cast(&mut self, value: Value, from: Ty, to: Ty) -> Value {
+        let from_type = self.builder.func.dfg.value_type(value);
+        let to_type = cranelift_type(to, self.int);
+        if from.is_float() || to.is_float() {
+            self.translate_float_cast(value, from_type, to_type)
+        } else if to == Ty::Bool && from != Ty::Bool {
+            self.builder.ins().icmp_imm(IntCC::NotEqual, value, 0)
+        } else if from_type == to_type {
+            value
+        } else if from_type.bits() > to_type.bits() {
+            self.builder.ins().ireduce(to_type, value)
+        } else if from.is_integer() {
+            self.builder.ins().sextend(to_type, value)
+        } else {
+            self.builder.ins().uextend(to_type, value)
+        }
+    }
+
+    fn translate_float(&mut self, literal: String, ty: Option<Ty>) -> Value {
+        // Type checking has made sure that the literal is finite in its type.
+        let text = literal.replace('_', "");
+        match ty.unwrap() {
+            Ty::F32 => self.builder.ins().f32const(text.parse::<f32>().unwrap()),
+            _ => self.builder.ins().f64const(text.parse::<f64>().unwrap()),
         }
     }
 
-    fn translate_assign(&mut self, name: String, expr: Expr) -> Value {
+    /// Produce the address of a NUL-terminated copy of `string`, creating a
+    /// read-only data object to hold it unless there's one already.
+    fn translate_string(&mut self, string: String) -> Result<Value, JitError> {
+        let id = match self.data_objects.strings.get(&string) {
+            Some(&id) => id,
+            None => {
+                let id = self.module.declare_anonymous_data(false, false)?;
+                let mut contents = string.clone().into_bytes();
+                contents.push(0);
+                let mut description = DataDescription::new();
+                description.define(contents.into_boxed_slice());
+                self.module.define_data(id, &description)?;
+
+                self.data_objects.strings.insert(string.clone(), id);
+                self.data_objects
+                    .unregistered
+                    .push((format!("{string:?}"), id, false));
+                id
+            }
+        };
+        let local_id = self.module.declare_data_in_func(id, self.builder.func);
+        Ok(self.builder.ins().symbol_value(self.int, local_id))
+    }
+
+    fn translate_assign(
+        &mut self,
+        name: String,
+        expr: Expr,
+        span: Span,
+    ) -> Result<Value, JitError> {
+        let new_value = self.translate_expr(expr)?;
+        self.assign(name, new_value, span)?;
+        Ok(new_value)
+    }
+
+    /// Write `value` to the variable, or failing that the global, `name`.
+    fn assign(&mut self, name: String, value: Value, span: Span) -> Result<(), JitError> {
         // `def_var` is used to write the value of a variable. Note that
         // variables can have multiple definitions. Cranelift will
         // convert them into SSA form for itself automatically.
-        let new_value = self.translate_expr(expr);
-        let variable = self.variables.get(&name).unwrap();
-        self.builder.def_var(*variable, new_value);
-        new_value
+        if let Some(&variable) = self.variables.get(&name) {
+            self.builder.def_var(variable, value);
+            return Ok(());
+        }
+        if !self.globals.contains_key(&name) {
+            return Err(JitError::UndefinedVariable { name, span });
+        }
+        let address = self.translate_global_data_addr(name)?;
+        self.builder
+            .ins()
+            .store(MemFlags::trusted(), value, address, 0);
+        Ok(())
     }
 
-    fn translate_icmp(&mut self, cmp: IntCC, lhs: Expr, rhs: Expr) -> Value {
-        let lhs = self.translate_expr(lhs);
-        let rhs = self.translate_expr(rhs);
-        self.builder.ins().icmp(cmp, lhs, rhs)
+    /// Read the global `name`. A constant's value is known at compile time,
+    /// so it's used directly, while a global variable is loaded from its
+    /// data object.
+    fn translate_global(&mut self, name: String, span: Span) -> Result<Value, JitError> {
+        let globals = self.globals;
+        let Some(global) = globals.get(&name) else {
+            return Err(JitError::UndefinedVariable { name, span });
+        };
+        let ty = cranelift_type(global.ty, self.int);
+        let Some(bytes) = &global.constant else {
+            let address = self.translate_global_data_addr(name)?;
+            return Ok(self.builder.ins().load(ty, MemFlags::trusted(), address, 0));
+        };
+        Ok(match ty {
+            types::F32 => self
+                .builder
+                .ins()
+                .f32const(f32::from_le_bytes(bytes[..].try_into().unwrap())),
+            types::F64 => self
+                .builder
+                .ins()
+                .f64const(f64::from_le_bytes(bytes[..].try_into().unwrap())),
+            _ => {
+                let mut imm = [0; 8];
+                imm[..bytes.len()].copy_from_slice(bytes);
+                self.iconst(ty, i64::from_le_bytes(imm))
+            }
+        })
     }
 
-    fn translate_if_else(
+    fn translate_tuple_assign(
         &mut self,
-        condition: Expr,
-        then_body: Vec<Expr>,
-        else_body: Vec<Expr>,
+        names: Vec<String>,
+        expr: Expr,
+        span: Span,
+    ) -> Result<Value, JitError> {
+        // Only calls can produce more than one value; anything else produces
+        // exactly one.
+        let values = match expr.kind {
+            ExprKind::Call(name, args) => self.translate_call(name, args)?,
+            _ => vec![self.translate_expr(expr)?],
+        };
+        for (name, value) in names.into_iter().zip(values) {
+            self.assign(name, value, span)?;
+        }
+
+        Ok(self.unit_value())
+    }
+
+    /// Translate a comparison, using `icmp` with the condition `cmp` to
+    /// compare integers, or `fcmp` with the equivalent condition to compare
+    /// floats.
+    fn translate_comparison(
+        &mut self,
+        cmp: IntCC,
+        lhs: Expr,
+        rhs: Expr,
+    ) -> Result<Value, JitError> {
+        let float = is_float(lhs.ty);
+        let lhs = self.translate_expr(lhs)?;
+        let rhs = self.translate_expr(rhs)?;
+
+        // Both produce an `i8` which is 0 or 1, which is how `bool`s are
+        // represented.
+        if !float {
+            return Ok(self.builder.ins().icmp(cmp, lhs, rhs));
+        }
+
+        // As in Rust, every comparison involving a NaN is false except `!=`.
+        let cmp = match cmp {
+            IntCC::Equal => FloatCC::Equal,
+            IntCC::NotEqual => FloatCC::NotEqual,
+            IntCC::SignedLessThan => FloatCC::LessThan,
+            IntCC::SignedLessThanOrEqual => FloatCC::LessThanOrEqual,
+            IntCC::SignedGreaterThan => FloatCC::GreaterThan,
+            IntCC::SignedGreaterThanOrEqual => FloatCC::GreaterThanOrEqual,
+            _ => unreachable!("{cmp} is not supported on floats"),
+        };
+        Ok(self.builder.ins().fcmp(cmp, lhs, rhs))
+    }
+
+    /// Convert `value` between a float and an integer, or between floats of
+    /// different sizes. As in Rust, a float which doesn't fit in the integer
+    /// type saturates, and NaN becomes 0.
+    fn translate_float_cast(
+        &mut self,
+        value: Value,
+        from_type: types::Type,
+        to_type: types::Type,
     ) -> Value {
-        let condition_value = self.translate_expr(condition);
+        if from_type == to_type {
+            value
+        } else if !from_type.is_float() {
+            self.builder.ins().fcvt_from_sint(to_type, value)
+        } else if to_type.is_float() && to_type.bits() > from_type.bits() {
+            self.builder.ins().fpromote(to_type, value)
+        } else if to_type.is_float() {
+            self.builder.ins().fdemote(to_type, value)
+        } else if to_type.bits() >= 32 {
+            self.builder.ins().fcvt_to_sint_sat(to_type, value)
+        } else {
+            // Cranelift only converts floats to 32- and 64-bit integers, so
+            // saturate to the range of the narrower type by hand.
+            let wide = self.builder.ins().fcvt_to_sint_sat(types::I32, value);
+            let min = i64::MIN >> (64 - to_type.bits());
+            let max = i64::MAX >> (64 - to_type.bits());
+            let min = self.iconst(types::I32, min);
+            let max = self.iconst(types::I32, max);
+            let clamped = self.builder.ins().smax(wide, min);
+            let clamped = self.builder.ins().smin(clamped, max);
+            self.builder.ins().ireduce(to_type, clamped)
+        }
+    }
 
-        let then_block = self.builder.create_block();
-        let else_block = self.builder.create_block();
-        let merge_block = self.builder.create_block();
+    /// Translate a division or remainder. Rather than letting the processor
+    /// trap, dividing by zero, or dividing the most negative value of a type
+    /// by -1, reports a trap to the runtime and returns from the function.
+    fn translate_division(
+        &mut self,
+        opcode: Opcode,
+        lhs: Expr,
+        rhs: Expr,
+    ) -> Result<Value, JitError> {
+        let lhs = self.translate_expr(lhs)?;
+        let rhs = self.translate_expr(rhs)?;
+        let ty = self.builder.func.dfg.value_type(lhs);
+
+        let zero_block = self.builder.create_block();
+        let divide_block = self.builder.create_block();
+
+        self.builder
+            .ins()
+            .brif(rhs, divide_block, &[], zero_block, &[]);
+
+        self.builder.switch_to_block(zero_block);
+        self.builder.seal_block(zero_block);
+        self.translate_trap(Trap::DivisionByZero)?;
+
+        self.builder.switch_to_block(divide_block);
+        self.builder.seal_block(divide_block);
+
+        // Only signed division can overflow; the remainder of the most
+        // negative value divided by -1 is simply 0.
+        if opcode == Opcode::Sdiv {
+            let overflow_block = self.builder.create_block();
+            let no_overflow_block = self.builder.create_block();
+
+            let min = self.iconst(ty, i64::MIN >> (64 - ty.bits()));
+            let minus_one = self.iconst(ty, -1);
+            let is_min = self.builder.ins().icmp(IntCC::Equal, lhs, min);
+            let is_minus_one = self.builder.ins().icmp(IntCC::Equal, rhs, minus_one);
+            let overflows = self.builder.ins().band(is_min, is_minus_one);
+            self.builder
+                .ins()
+                .brif(overflows, overflow_block, &[], no_overflow_block, &[]);
+
+            self.builder.switch_to_block(overflow_block);
+            self.builder.seal_block(overflow_block);
+            self.translate_trap(Trap::DivisionOverflow)?;
+
+            self.builder.switch_to_block(no_overflow_block);
+            self.builder.seal_block(no_overflow_block);
+        }
+
+        Ok(match opcode {
+            Opcode::Sdiv => self.builder.ins().sdiv(lhs, rhs),
+            Opcode::Srem => self.builder.ins().srem(lhs, rhs),
+            Opcode::Udiv => self.builder.ins().udiv(lhs, rhs),
+            Opcode::Urem => self.builder.ins().urem(lhs, rhs),
+            _ => unreachable!("{opcode} is not a division"),
+        })
+    }
+
+    /// Report `trap` to the runtime, then return from the function, so that
+    /// nothing runs after the fault.
+    fn translate_trap(&mut self, trap: Trap) -> Result<(), JitError> {
+        let pointer = self.module.target_config().pointer_type();
 
-        // If-else constructs in the toy language have a return value.
-        // In traditional SSA form, this would produce a PHI between
-        // the then and else bodies. Cranelift uses block parameters,
-        // so set up a parameter in the merge block, and we'll pass
-        // the return values to it from the branches.
-        self.builder.append_block_param(merge_block, self.int);
+        let mut sig = self.module.make_signature();
+        sig.params.push(AbiParam::new(pointer));
+        sig.params.push(AbiParam::new(types::I64));
+        let callee = self
+            .module
+            .declare_function(runtime::TRAP_SYMBOL, Linkage::Import, &sig)?;
+        let local_callee = self.module.declare_func_in_func(callee, self.builder.func);
 
-        // Test the if condition and conditionally branch.
+        let runtime = self.builder.ins().iconst(pointer, self.runtime as i64);
+        let code = self.builder.ins().iconst(types::I64, trap.code());
+        self.builder.ins().call(local_callee, &[runtime, code]);
+        self.return_after_trap();
+        Ok(())
+    }
+
+    /// Return from the function after a trap, with whatever values the
+    /// return variables hold. The caller, if it's JIT'd code, finds the trap
+    /// when the call returns, and returns in turn.
+    fn return_after_trap(&mut self) {
+        let return_values = self.use_return_variables();
+        self.builder.ins().return_(&return_values);
+    }
+
+    /// Return from the function if `value` is zero, in which case a trap has
+    /// been reported, and otherwise carry on.
+    fn return_if_zero(&mut self, value: Value) {
+        let trap_block = self.builder.create_block();
+        let continue_block = self.builder.create_block();
         self.builder
             .ins()
-            .brif(condition_value, then_block, &[], else_block, &[]);
+            .brif(value, continue_block, &[], trap_block, &[]);
+
+        self.builder.switch_to_block(trap_block);
+        self.builder.seal_block(trap_block);
+        self.return_after_trap();
+
+        self.builder.switch_to_block(continue_block);
+        self.builder.seal_block(continue_block);
+    }
 
-        self.builder.switch_to_block(then_block);
-        self.builder.seal_block(then_block);
-        let mut then_return = self.builder.ins().iconst(self.int, 0);
-        for expr in then_body {
-            then_return = self.translate_expr(expr);
+    /// Produce the address to use for a load, or a store if `store` is set,
+    /// of a value of type `ty` at `address`. If bounds checks are enabled,
+    /// the runtime checks the access, and if it's out of bounds, reports a
+    /// trap, and the function returns instead of making it.
+    fn translate_address(
+        &mut self,
+        address: Value,
+        ty: types::Type,
+        store: bool,
+    ) -> Result<Value, JitError> {
+        if !self.bounds_checks {
+            return Ok(address);
         }
+        let pointer = self.module.target_config().pointer_type();
 
-        // Jump to the merge block, passing it the block return value.
+        let mut sig = self.module.make_signature();
+        sig.params.push(AbiParam::new(pointer));
+        sig.params.push(AbiParam::new(pointer));
+        sig.params.push(AbiParam::new(pointer));
+        sig.params.push(AbiParam::new(pointer));
+        sig.returns.push(AbiParam::new(pointer));
+        let callee =
+            self.module
+                .declare_function(runtime::CHECK_ACCESS_SYMBOL, Linkage::Import, &sig)?;
+        let local_callee = self.module.declare_func_in_func(callee, self.builder.func);
+
+        let runtime = self.builder.ins().iconst(pointer, self.runtime as i64);
+        let width = self.builder.ins().iconst(pointer, i64::from(ty.bytes()));
+        let store = self.builder.ins().iconst(pointer, i64::from(store));
+        let call = self
+            .builder
+            .ins()
+            .call(local_callee, &[runtime, address, width, store]);
+        let address = self.builder.inst_results(call)[0];
+        self.return_if_zero(address);
+        Ok(address)
+    }
+
+    /// Allocate a stack slot for a local array, and zero it.
+    fn translate_array_decl(&mut self, name: String, ty: Ty, len: u32) -> Value {
+        // Type checking has made sure that the size fits in a `u32`.
+        let ty = cranelift_type(ty, self.int);
+        let size = len * ty.bytes();
+        let align_shift = ty.bytes().trailing_zeros() as u8;
+        let slot = self.builder.create_sized_stack_slot(StackSlotData::new(
+            StackSlotKind::ExplicitSlot,
+            size,
+            align_shift,
+        ));
+
+        let address = self.builder.ins().stack_addr(self.int, slot, 0);
+        let config = self.module.target_config();
+        self.builder.emit_small_memset(
+            config,
+            address,
+            0,
+            u64::from(size),
+            ty.bytes() as u8,
+            MemFlags::trusted(),
+        );
+        self.arrays.insert(name, Array { slot, ty, len });
+
+        self.unit_value()
+    }
+
+    /// Read the element of the array `name` at `index`, or, if there's a
+    /// `value`, write it to that element. If bounds checks are enabled, an
+    /// index past the end of the array reports a trap to the runtime instead,
+    /// and returns from the function.
+    fn translate_index(
+        &mut self,
+        name: String,
+        index: Expr,
+        value: Option<Expr>,
+    ) -> Result<Value, JitError> {
+        let array = self.arrays[&name];
+        let index = self.translate_expr(index)?;
+        let value = value.map(|value| self.translate_expr(value)).transpose()?;
+
+        let base = self.builder.ins().stack_addr(self.int, array.slot, 0);
+        let offset = self
+            .builder
+            .ins()
+            .imul_imm(index, i64::from(array.ty.bytes()));
+        let address = self.builder.ins().iadd(base, offset);
+        if !self.bounds_checks {
+            return Ok(self.translate_element_access(array, address, value));
+        }
+
+        let access_block = self.builder.create_block();
+        let trap_block = self.builder.create_block();
+
+        // Comparing as unsigned catches negative indices too.
+        let in_bounds =
+            self.builder
+                .ins()
+                .icmp_imm(IntCC::UnsignedLessThan, index, i64::from(array.len));
         self.builder
             .ins()
-            .jump(merge_block, &[BlockArg::Value(then_return)]);
+            .brif(in_bounds, access_block, &[], trap_block, &[]);
 
-        self.builder.switch_to_block(else_block);
-        self.builder.seal_block(else_block);
-        let mut else_return = self.builder.ins().iconst(self.int, 0);
-        for expr in else_body {
-            else_return = self.translate_expr(expr);
+        self.builder.switch_to_block(trap_block);
+        self.builder.seal_block(trap_block);
+        self.translate_trap(Trap::IndexOutOfBounds)?;
+
+        self.builder.switch_to_block(access_block);
+        self.builder.seal_block(access_block);
+        Ok(self.translate_element_access(array, address, value))
+    }
+
+    /// Load the element of `array` at `address`, or store `value` to it.
+    /// Either way, produce the element's new value.
+    fn translate_element_access(
+        &mut self,
+        array: Array,
+        address: Value,
+        value: Option<Value>,
+    ) -> Value {
+        match value {
+            Some(value) => {
+                self.builder.ins().store(memory_flags(), value, address, 0);
+                value
+            }
+            None => self
+                .builder
+                .ins()
+                .load(array.ty, memory_flags(), address, 0),
+        }
+    }
+
+    /// Read the field `field` of the struct which `object` points to, or, if
+    /// there's a `value`, write it to the field. Either way, produce the
+    /// field's new value.
+    fn translate_field(
+        &mut self,
+        object: Expr,
+        field: String,
+        value: Option<Expr>,
+    ) -> Result<Value, JitError> {
+        let Some(Ty::Ref(name)) = object.ty else {
+            unreachable!("type checking only allows field accesses on struct references");
+        };
+        let field = self.structs[name].field(&field).unwrap();
+        let (offset, ty) = (field.offset, cranelift_type(field.ty, self.int));
+
+        let object = self.translate_expr(object)?;
+        let value = value.map(|value| self.translate_expr(value)).transpose()?;
+        let address = self.builder.ins().iadd_imm(object, i64::from(offset));
+        let address = self.translate_address(address, ty, value.is_some())?;
+        Ok(match value {
+            Some(value) => {
+                self.builder.ins().store(memory_flags(), value, address, 0);
+                value
+            }
+            None => self.builder.ins().load(ty, memory_flags(), address, 0),
+        })
+    }
+
+    /// Translate `lhs && rhs` (when `is_and` is set) or `lhs || rhs`. The
+    /// right-hand side is only evaluated if the left-hand side doesn't
+    /// already determine the result.
+    fn translate_short_circuit(
+        &mut self,
+        is_and: bool,
+        lhs: Expr,
+        rhs: Expr,
+    ) -> Result<Value, JitError> {
+        let lhs_value = self.translate_expr(lhs)?;
+
+        let rhs_block = self.builder.create_block();
+        let merge_block = self.builder.create_block();
+
+        // As with if-else, the result is passed to the merge block as a
+        // block parameter, either directly from the test of the left-hand
+        // side, or from the block which evaluates the right-hand side.
+        self.builder.append_block_param(merge_block, types::I8);
+
+        if is_and {
+            let zero = self.builder.ins().iconst(types::I8, 0);
+            self.builder.ins().brif(
+                lhs_value,
+                rhs_block,
+                &[],
+                merge_block,
+                &[BlockArg::Value(zero)],
+            );
+        } else {
+            let one = self.builder.ins().iconst(types::I8, 1);
+            self.builder.ins().brif(
+                lhs_value,
+                merge_block,
+                &[BlockArg::Value(one)],
+                rhs_block,
+                &[],
+            );
         }
 
-        // Jump to the merge block, passing it the block return value.
+        self.builder.switch_to_block(rhs_block);
+        self.builder.seal_block(rhs_block);
+        let rhs_value = self.translate_expr(rhs)?;
+        let rhs_is_true = self.builder.ins().icmp_imm(IntCC::NotEqual, rhs_value, 0);
         self.builder
             .ins()
-            .jump(merge_block, &[BlockArg::Value(else_return)]);
+            .jump(merge_block, &[BlockArg::Value(rhs_is_true)]);
+
+        self.builder.switch_to_block(merge_block);
+        self.builder.seal_block(merge_block);
+        Ok(self.builder.block_params(merge_block)[0])
+    }
+
+    fn translate_if_else(
+        &mut self,
+        branches: Vec<(Expr, Vec<Expr>)>,
+        else_body: Vec<Expr>,
+        ty: Option<Ty>,
+    ) -> Result<Value, JitError> {
+        let merge_block = self.builder.create_block();
+
+        // If-else constructs in the toy language have a return value, as
+        // long as all of the branches produce the same type. In traditional
+        // SSA form, this would produce a PHI between the then and else
+        // bodies. Cranelift uses block parameters, so set up a parameter in
+        // the merge block, and we'll pass the return values to it from the
+        // branches.
+        let ty = ty.map(|ty| cranelift_type(ty, self.int));
+        if let Some(ty) = ty {
+            self.builder.append_block_param(merge_block, ty);
+        }
+
+        // An `else if` chain is translated as a flat sequence of tests, each
+        // of which falls through to the next when its condition is false,
+        // with every body jumping to the same merge block.
+        for (condition, then_body) in branches {
+            let condition_value = self.translate_expr(condition)?;
+
+            let then_block = self.builder.create_block();
+            let else_block = self.builder.create_block();
+
+            // Test the if condition and conditionally branch.
+            self.builder
+                .ins()
+                .brif(condition_value, then_block, &[], else_block, &[]);
+
+            self.builder.switch_to_block(then_block);
+            self.builder.seal_block(then_block);
+            let mut then_return = None;
+            for expr in then_body {
+                then_return = Some(self.translate_expr(expr)?);
+            }
+
+            // Jump to the merge block, passing it the block return value.
+            self.jump_to_merge_block(merge_block, ty, then_return);
+
+            self.builder.switch_to_block(else_block);
+            self.builder.seal_block(else_block);
+        }
+
+        // A missing `else` behaves like an empty one.
+        let mut else_return = None;
+        for expr in else_body {
+            else_return = Some(self.translate_expr(expr)?);
+        }
+
+        // Jump to the merge block, passing it the block return value.
+        self.jump_to_merge_block(merge_block, ty, else_return);
 
         // Switch to the merge block for subsequent statements.
         self.builder.switch_to_block(merge_block);
@@ -320,20 +1571,135 @@ impl<'a> FunctionTranslator<'a> {
 
         // Read the value of the if-else by reading the merge block
         // parameter.
-        let phi = self.builder.block_params(merge_block)[0];
+        match ty {
+            Some(_) => Ok(self.builder.block_params(merge_block)[0]),
+            None => Ok(self.builder.ins().iconst(self.int, 0)),
+        }
+    }
+
+    fn translate_match(
+        &mut self,
+        scrutinee: Expr,
+        arms: Vec<MatchArm>,
+        ty: Option<Ty>,
+    ) -> Result<Value, JitError> {
+        let value = self.translate_expr(scrutinee)?;
 
-        phi
+        let merge_block = self.builder.create_block();
+        let ty = ty.map(|ty| cranelift_type(ty, self.int));
+        if let Some(ty) = ty {
+            self.builder.append_block_param(merge_block, ty);
+        }
+
+        // `Switch` picks the best way to dispatch on the values it's given:
+        // a jump table for a dense run of them, and a tree of comparisons
+        // otherwise. Values are given to it zero-extended from the width of
+        // the scrutinee.
+        let mut switch = Switch::new();
+        let mut default_block = None;
+        let mut bodies = Vec::new();
+        for arm in arms {
+            let block = self.builder.create_block();
+            for pattern in arm.patterns {
+                match pattern {
+                    Pattern::Wildcard(_) => default_block = Some(block),
+                    Pattern::Value(expr) => {
+                        // Type checking has made sure that no value appears
+                        // twice.
+                        let entry = self.pattern_value(&expr)?;
+                        switch.set_entry(entry, block);
+                    }
+                }
+            }
+            bodies.push((block, arm.body));
+        }
+
+        // A missing `_` arm behaves like an empty one.
+        let default_block = default_block.unwrap_or_else(|| {
+            let block = self.builder.create_block();
+            bodies.push((block, Vec::new()));
+            block
+        });
+        switch.emit(&mut self.builder, value, default_block);
+
+        for (block, body) in bodies {
+            self.builder.switch_to_block(block);
+            self.builder.seal_block(block);
+            let mut body_return = None;
+            for expr in body {
+                body_return = Some(self.translate_expr(expr)?);
+            }
+            self.jump_to_merge_block(merge_block, ty, body_return);
+        }
+
+        self.builder.switch_to_block(merge_block);
+        self.builder.seal_block(merge_block);
+        match ty {
+            Some(_) => Ok(self.builder.block_params(merge_block)[0]),
+            None => Ok(self.builder.ins().iconst(self.int, 0)),
+        }
+    }
+
+    /// The value which the constant `expr`, a pattern of a match, matches,
+    /// zero-extended from the width of its type.
+    fn pattern_value(&self, expr: &Expr) -> Result<u128, JitError> {
+        let bytes = match &expr.kind {
+            ExprKind::Identifier(name) => self.globals[name].constant.clone().unwrap(),
+            _ => constant_bytes(expr, expr.ty.unwrap(), self.int.bytes())?,
+        };
+        let mut value = [0; 16];
+        value[..bytes.len()].copy_from_slice(&bytes);
+        Ok(u128::from_le_bytes(value))
+    }
+
+    /// Jump from the end of a branch of an if-else or arm of a match to
+    /// `merge_block`, passing it the value of the branch if the expression
+    /// produces a value of type `ty`. A branch with an empty body produces
+    /// zero.
+    fn jump_to_merge_block(
+        &mut self,
+        merge_block: Block,
+        ty: Option<types::Type>,
+        value: Option<Value>,
+    ) {
+        match ty {
+            Some(ty) => {
+                let value = match value {
+                    Some(value) => value,
+                    None => zero(&mut self.builder, ty),
+                };
+                self.builder
+                    .ins()
+                    .jump(merge_block, &[BlockArg::Value(value)]);
+            }
+            None => {
+                self.builder.ins().jump(merge_block, &[]);
+            }
+        }
     }
 
-    fn translate_while_loop(&mut self, condition: Expr, loop_body: Vec<Expr>) -> Value {
+    fn translate_while_loop(
+        &mut self,
+        label: Option<String>,
+        condition: Expr,
+        loop_body: Vec<Expr>,
+    ) -> Result<Value, JitError> {
         let header_block = self.builder.create_block();
         let body_block = self.builder.create_block();
         let exit_block = self.builder.create_block();
 
+        // Within the body, `continue` jumps back to the header to test the
+        // condition again, and `break` jumps straight to the exit.
+        self.loops.push(Loop {
+            label,
+            continue_block: header_block,
+            break_block: exit_block,
+        });
+
         self.builder.ins().jump(header_block, &[]);
         self.builder.switch_to_block(header_block);
 
-        let condition_value = self.translate_expr(condition);
+        let condition_value = self.translate_expr(condition)?;
         self.builder
             .ins()
             .brif(condition_value, body_block, &[], exit_block, &[]);
@@ -342,8 +1708,9 @@ impl<'a> FunctionTranslator<'a> {
         self.builder.seal_block(body_block);
 
         for expr in loop_body {
-            self.translate_expr(expr);
+            self.translate_expr(expr)?;
         }
+        self.loops.pop();
         self.builder.ins().jump(header_block, &[]);
 
         self.builder.switch_to_block(exit_block);
@@ -354,108 +1721,393 @@ impl<'a> FunctionTranslator<'a> {
         self.builder.seal_block(exit_block);
 
         // Just return 0 for now.
-        self.builder.ins().iconst(self.int, 0)
+        Ok(self.builder.ins().iconst(self.int, 0))
     }
 
-    fn translate_call(&mut self, name: String, args: Vec<Expr>) -> Value {
-        let mut sig = self.module.make_signature();
+    /// Translate a `for` loop. The end of the range and the step are held
+    /// in hidden variables, so that they're only evaluated once:
+    ///
+    /// ```text
+    /// var = start
+    /// if step > 0 {
+    ///     while var < end {  // or `<=` for an inclusive range
+    ///         body
+    ///         var = var + step, or stop if that overflows
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// Stopping on overflow means that a range which ends at, or near, the
+    /// largest value of its type still ends. A step which isn't positive
+    /// would never reach the end, so the loop runs no iterations instead.
+    #[allow(clippy::too_many_arguments)]
+    fn translate_for_loop(
+        &mut self,
+        label: Option<String>,
+        var: String,
+        start: Expr,
+        end: Expr,
+        inclusive: bool,
+        step: Option<Box<Expr>>,
+        body: Vec<Expr>,
+        span: Span,
+    ) -> Result<Value, JitError> {
+        self.translate_assign(var.clone(), start, span)?;
+        let end_value = self.translate_expr(end)?;
+        let end = self.hidden_variable(end_value);
+        let step_value = match step {
+            Some(step) => self.translate_expr(*step)?,
+            None => {
+                let var_type = self.builder.func.dfg.value_type(end_value);
+                self.builder.ins().iconst(var_type, 1)
+            }
+        };
+        let step = self.hidden_variable(step_value);
+        let var = self.variables[&var];
+
+        let header_block = self.builder.create_block();
+        let body_block = self.builder.create_block();
+        let step_block = self.builder.create_block();
+        let exit_block = self.builder.create_block();
+
+        // `continue` jumps to the step, so that it's run after every
+        // iteration.
+        self.loops.push(Loop {
+            label,
+            continue_block: step_block,
+            break_block: exit_block,
+        });
 
-        // Add a parameter for each argument.
-        for _arg in &args {
-            sig.params.push(AbiParam::new(self.int));
+        let positive = self
+            .builder
+            .ins()
+            .icmp_imm(IntCC::SignedGreaterThan, step_value, 0);
+        self.builder
+            .ins()
+            .brif(positive, header_block, &[], exit_block, &[]);
+
+        self.builder.switch_to_block(header_block);
+        let compare = if inclusive {
+            IntCC::SignedLessThanOrEqual
+        } else {
+            IntCC::SignedLessThan
+        };
+        let var_value = self.builder.use_var(var);
+        let end_value = self.builder.use_var(end);
+        let condition_value = self.builder.ins().icmp(compare, var_value, end_value);
+        self.builder
+            .ins()
+            .brif(condition_value, body_block, &[], exit_block, &[]);
+
+        self.builder.switch_to_block(body_block);
+        self.builder.seal_block(body_block);
+        for expr in body {
+            self.translate_expr(expr)?;
         }
+        self.loops.pop();
+        self.builder.ins().jump(step_block, &[]);
+
+        // All of the `continue`s have been seen, so the step block has all
+        // of its predecessors.
+        self.builder.switch_to_block(step_block);
+        self.builder.seal_block(step_block);
+        let var_value = self.builder.use_var(var);
+        let step_value = self.builder.use_var(step);
+        let (next, overflow) = self.builder.ins().sadd_overflow(var_value, step_value);
+        self.builder.def_var(var, next);
+        self.builder
+            .ins()
+            .brif(overflow, exit_block, &[], header_block, &[]);
 
-        // For simplicity for now, just make all calls return a single I64.
-        sig.returns.push(AbiParam::new(self.int));
+        self.builder.switch_to_block(exit_block);
+        self.builder.seal_block(header_block);
+        self.builder.seal_block(exit_block);
 
-        // TODO: Streamline the API here?
-        let callee = self
-            .module
-            .declare_function(&name, Linkage::Import, &sig)
-            .expect("problem declaring function");
-        let local_callee = self.module.declare_func_in_func(callee, self.builder.func);
+        // Just return 0 for now.
+        Ok(self.builder.ins().iconst(self.int, 0))
+    }
+
+    /// Declare a variable holding `value`, which the source can't refer to.
+    fn hidden_variable(&mut self, value: Value) -> Variable {
+        let ty = self.builder.func.dfg.value_type(value);
+        let variable = self.builder.declare_var(ty);
+        self.builder.def_var(variable, value);
+        variable
+    }
+
+    /// Find the loop which a `break` or `continue` refers to: the innermost
+    /// loop with the given label, or the innermost loop if there's no label.
+    /// Type checking has made sure that there is one.
+    fn find_loop(&self, label: Option<String>) -> &Loop {
+        let found = match &label {
+            Some(label) => self
+                .loops
+                .iter()
+                .rev()
+                .find(|l| l.label.as_ref() == Some(label)),
+            None => self.loops.last(),
+        };
+        found.unwrap()
+    }
+
+    /// Jump to `target`, for a `break` or `continue`.
+    fn translate_jump_out(&mut self, target: Block) -> Value {
+        self.builder.ins().jump(target, &[]);
+        self.switch_to_unreachable_block();
+
+        self.unit_value()
+    }
+
+    /// Return from the function, with the given values if there are any, or
+    /// else with the current values of the return variables.
+    fn translate_return(&mut self, values: Vec<Expr>) -> Result<Value, JitError> {
+        let return_values = if values.is_empty() {
+            self.use_return_variables()
+        } else {
+            let mut return_values = Vec::new();
+            for value in values {
+                return_values.push(self.translate_expr(value)?);
+            }
+            return_values
+        };
+        self.builder.ins().return_(&return_values);
+        self.switch_to_unreachable_block();
+        Ok(self.unit_value())
+    }
 
+    fn use_return_variables(&mut self) -> Vec<Value> {
+        self.return_variables
+            .iter()
+            .map(|&variable| self.builder.use_var(variable))
+            .collect()
+    }
+
+    /// After a jump out of the middle of a block, any code which follows in
+    /// the same block can never run, but it still has to be translated into
+    /// a block which is properly terminated. Switch to a new block with no
+    /// predecessors to hold it; Cranelift will discard it as unreachable.
+    fn switch_to_unreachable_block(&mut self) {
+        let unreachable_block = self.builder.create_block();
+        self.builder.switch_to_block(unreachable_block);
+        self.builder.seal_block(unreachable_block);
+    }
+
+    /// Stores, declarations, tuple assignments, `break`, `continue` and
+    /// `return` don't produce a value, but like loops every expression has to
+    /// evaluate to something.
+    fn unit_value(&mut self) -> Value {
+        self.builder.ins().iconst(self.int, 0)
+    }
+
+    fn translate_call(&mut self, name: String, args: Vec<Expr>) -> Result<Vec<Value>, JitError> {
         let mut arg_values = Vec::new();
         for arg in args {
-            arg_values.push(self.translate_expr(arg))
+            arg_values.push(self.translate_expr(arg)?)
         }
+
+        // Type checking has made sure that the arguments match the callee's
+        // type, which for a host function which wasn't declared with `extern
+        // fn` is the one inferred from its first call.
+        let sig = make_signature(self.module, &self.functions[&name]);
+
+        // TODO: Streamline the API here?
+        let callee = self.module.declare_function(&name, Linkage::Import, &sig)?;
+        let local_callee = self.module.declare_func_in_func(callee, self.builder.func);
+
         let call = self.builder.ins().call(local_callee, &arg_values);
-        self.builder.inst_results(call)[0]
+        let results = self.builder.inst_results(call).to_vec();
+
+        // If the callee, or anything it called, raised a trap, return
+        // straight away too, so that nothing runs after the fault.
+        let pointer = self.module.target_config().pointer_type();
+        let runtime = self.builder.ins().iconst(pointer, self.runtime as i64);
+        let trapped = self.builder.ins().load(
+            types::I8,
+            MemFlags::trusted(),
+            runtime,
+            Runtime::trapped_offset(),
+        );
+        let ok = self.builder.ins().icmp_imm(IntCC::Equal, trapped, 0);
+        self.return_if_zero(ok);
+        Ok(results)
     }
 
-    fn translate_global_data_addr(&mut self, name: String) -> Value {
+    fn translate_global_data_addr(&mut self, name: String) -> Result<Value, JitError> {
         let sym = self
             .module
-            .declare_data(&name, Linkage::Export, true, false)
-            .expect("problem declaring data object");
+            .declare_data(&name, Linkage::Export, true, false)?;
         let local_id = self.module.declare_data_in_func(sym, self.builder.func);
 
         let pointer = self.module.target_config().pointer_type();
-        self.builder.ins().symbol_value(pointer, local_id)
+        Ok(self.builder.ins().symbol_value(pointer, local_id))
+    }
+}
+
+/// The type of a constant which isn't annotated with one, which is the type
+/// a literal has if the context doesn't call for a particular one.
+fn constant_type(expr: &Expr) -> Result<Ty, JitError> {
+    let kind = match &expr.kind {
+        ExprKind::Neg(operand) => &operand.kind,
+        kind => kind,
+    };
+    match kind {
+        ExprKind::Literal(_) => Ok(Ty::I64),
+        ExprKind::Float(_) => Ok(Ty::F64),
+        ExprKind::Bool(_) => Ok(Ty::Bool),
+        _ => Err(JitError::NotConstant { span: expr.span }),
+    }
+}
+
+/// Encode the value of `expr`, which must be a constant of type `ty`, as it's
+/// stored in memory on a target whose pointers are `pointer_bytes` bytes
+/// wide.
+fn constant_bytes(expr: &Expr, ty: Ty, pointer_bytes: u32) -> Result<Vec<u8>, JitError> {
+    let (kind, negate) = match &expr.kind {
+        ExprKind::Neg(operand) => (&operand.kind, true),
+        kind => (kind, false),
+    };
+    let size = layout::size_of(ty, pointer_bytes) as usize;
+    let mismatch = |found| JitError::TypeMismatch {
+        expected: ty,
+        found,
+        span: expr.span,
+    };
+    let out_of_range = |literal: &str| JitError::LiteralOutOfRange {
+        literal: if negate {
+            format!("-{literal}")
+        } else {
+            literal.to_owned()
+        },
+        span: expr.span,
+    };
+    match kind {
+        ExprKind::Literal(literal) if ty.is_integer() || ty.is_pointer() => {
+            let value = typeck::parse_literal(literal, negate, size as u32 * 8)
+                .ok_or_else(|| out_of_range(literal))?;
+            Ok(value.to_le_bytes()[..size].to_vec())
+        }
+        ExprKind::Literal(_) => Err(mismatch(Ty::I64)),
+        ExprKind::Float(literal) if ty.is_float() => {
+            let text = literal.replace('_', "");
+            let sign = if negate { -1.0 } else { 1.0 };
+            let bytes = match ty {
+                Ty::F32 => text
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|value| value.is_finite())
+                    .map(|value| (sign as f32 * value).to_le_bytes().to_vec()),
+                _ => text
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|value| value.is_finite())
+                    .map(|value| (sign * value).to_le_bytes().to_vec()),
+            };
+            bytes.ok_or_else(|| out_of_range(literal))
+        }
+        ExprKind::Float(_) => Err(mismatch(Ty::F64)),
+        ExprKind::Bool(value) if !negate && ty == Ty::Bool => Ok(vec![u8::from(*value)]),
+        ExprKind::Bool(_) if !negate => Err(mismatch(Ty::Bool)),
+        _ => Err(JitError::NotConstant { span: expr.span }),
     }
 }
 
 fn declare_variables(
-    int: types::Type,
+    pointer: types::Type,
     builder: &mut FunctionBuilder,
-    params: &[String],
-    the_return: &str,
-    stmts: &[Expr],
+    params: &[(String, Ty)],
+    returns: &[(String, Ty)],
+    types: &[(String, Ty)],
     entry_block: Block,
 ) -> HashMap<String, Variable> {
     let mut variables = HashMap::new();
-    for (i, name) in params.iter().enumerate() {
+    for (name, ty) in types {
+        let var = builder.declare_var(cranelift_type(*ty, pointer));
+        variables.insert(name.clone(), var);
+    }
+    for (i, (name, _ty)) in params.iter().enumerate() {
         // TODO: cranelift_frontend should really have an API to make it easy to set
         // up param variables.
         let val = builder.block_params(entry_block)[i];
-        let var = declare_variable(int, builder, &mut variables, name);
-        builder.def_var(var, val);
+        builder.def_var(variables[name], val);
     }
-    let zero = builder.ins().iconst(int, 0);
-    let return_variable = declare_variable(int, builder, &mut variables, the_return);
-    builder.def_var(return_variable, zero);
-    for expr in stmts {
-        declare_variables_in_stmt(int, builder, &mut variables, expr);
+    for (name, ty) in returns {
+        let zero = zero(builder, cranelift_type(*ty, pointer));
+        builder.def_var(variables[name], zero);
     }
 
     variables
 }
 
-/// Recursively descend through the AST, translating all implicit
-/// variable declarations.
-fn declare_variables_in_stmt(
-    int: types::Type,
-    builder: &mut FunctionBuilder,
-    variables: &mut HashMap<String, Variable>,
-    expr: &Expr,
-) {
-    match *expr {
-        Expr::Assign(ref name, _) => {
-            declare_variable(int, builder, variables, name);
-        }
-        Expr::IfElse(ref _condition, ref then_body, ref else_body) => {
-            for stmt in then_body {
-                declare_variables_in_stmt(int, builder, variables, stmt);
-            }
-            for stmt in else_body {
-                declare_variables_in_stmt(int, builder, variables, stmt);
-            }
-        }
-        Expr::WhileLoop(ref _condition, ref loop_body) => {
-            for stmt in loop_body {
-                declare_variables_in_stmt(int, builder, variables, stmt);
-            }
-        }
-        _ => (),
+/// Compute the Cranelift signature of a toy-language function of type `ty`.
+fn make_signature(module: &JITModule, ty: &FunctionType) -> Signature {
+    let pointer = module.target_config().pointer_type();
+
+    let mut sig = module.make_signature();
+    for ty in &ty.params {
+        sig.params.push(AbiParam::new(cranelift_type(*ty, pointer)));
+    }
+
+    for ty in &ty.returns {
+        sig.returns
+            .push(AbiParam::new(cranelift_type(*ty, pointer)));
     }
+    sig
 }
 
-/// Declare a single variable declaration.
-fn declare_variable(
-    int: types::Type,
-    builder: &mut FunctionBuilder,
-    variables: &mut HashMap<String, Variable>,
-    name: &str,
-) -> Variable {
-   *variables.entry(name.into()).or_insert_with(|| builder.declare_var(int))
+/// Whether the host process has a symbol called `name`, which JIT'd code
+/// can import. This looks the symbol up the same way as the JIT module.
+#[cfg(unix)]
+fn host_has_symbol(name: &str) -> bool {
+    let Ok(name) = std::ffi::CString::new(name) else {
+        return false;
+    };
+    // Safety: `name` is a valid NUL-terminated string.
+    let symbol = unsafe { libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr()) };
+    !symbol.is_null()
+}
+
+/// Elsewhere, the JIT module searches several system libraries, which this
+/// doesn't try to replicate, so a missing symbol is still only found when
+/// the functions are finalized.
+#[cfg(not(unix))]
+fn host_has_symbol(_name: &str) -> bool {
+    true
+}
+
+/// The Cranelift type used to represent values of type `ty`.
+fn cranelift_type(ty: Ty, pointer: types::Type) -> types::Type {
+    match ty {
+        Ty::I8 => types::I8,
+        Ty::I16 => types::I16,
+        Ty::I32 => types::I32,
+        Ty::I64 => types::I64,
+        Ty::F32 => types::F32,
+        Ty::F64 => types::F64,
+        // Cranelift has no boolean type; comparisons produce an `i8` which
+        // is 0 or 1.
+        Ty::Bool => types::I8,
+        Ty::Ptr | Ty::Ref(_) => pointer,
+    }
+}
+
+/// Whether the type of an expression, as found by type checking, is a
+/// floating-point type.
+fn is_float(ty: Option<Ty>) -> bool {
+    ty.is_some_and(Ty::is_float)
+}
+
+/// The flags for the loads and stores which scripts perform. Nothing is known
+/// about the addresses they use, so these don't promise that the address is
+/// aligned or that the access can't trap.
+fn memory_flags() -> MemFlags {
+    MemFlags::new()
+}
+
+/// Produce a zero of type `ty`.
+fn zero(builder: &mut FunctionBuilder, ty: types::Type) -> Value {
+    match ty {
+        types::F32 => builder.ins().f32const(0.0),
+        types::F64 => builder.ins().f64const(0.0),
+        _ => builder.ins().iconst(ty, 0),
+    }
 }
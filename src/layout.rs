@@ -0,0 +1,71 @@
+//! The layout of structs in memory.
+//!
+//! Structs are laid out the same way as C lays them out: each field is
+//! placed at the next offset which is a multiple of its alignment, and the
+//! size of the struct is rounded up to a multiple of the largest alignment
+//! of any of its fields, so that structs can be placed in arrays. Every type
+//! is aligned to its own size.
+
+use crate::error::JitError;
+use crate::frontend::{Struct, Ty};
+
+/// The size and alignment of a struct, and the offsets of its fields.
+#[derive(Clone, Debug)]
+pub struct StructLayout {
+    pub size: u32,
+    pub align: u32,
+    pub fields: Vec<FieldLayout>,
+}
+
+#[derive(Clone, Debug)]
+pub struct FieldLayout {
+    pub name: String,
+    pub ty: Ty,
+    pub offset: u32,
+}
+
+impl StructLayout {
+    pub fn field(&self, name: &str) -> Option<&FieldLayout> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+}
+
+/// Lay out the struct declared by `decl`, on a target whose pointers are
+/// `pointer_bytes` bytes wide.
+pub fn layout_struct(decl: &Struct, pointer_bytes: u32) -> Result<StructLayout, JitError> {
+    let mut layout = StructLayout {
+        size: 0,
+        align: 1,
+        fields: Vec::new(),
+    };
+    for (name, ty) in &decl.fields {
+        if layout.field(name).is_some() {
+            return Err(JitError::Redefinition {
+                name: name.clone(),
+                span: decl.span,
+            });
+        }
+        let size = size_of(*ty, pointer_bytes);
+        let offset = layout.size.next_multiple_of(size);
+        layout.fields.push(FieldLayout {
+            name: name.clone(),
+            ty: *ty,
+            offset,
+        });
+        layout.size = offset + size;
+        layout.align = layout.align.max(size);
+    }
+    layout.size = layout.size.next_multiple_of(layout.align);
+    Ok(layout)
+}
+
+/// The size in bytes of a value of type `ty`, which is also its alignment.
+pub fn size_of(ty: Ty, pointer_bytes: u32) -> u32 {
+    match ty {
+        Ty::I8 | Ty::Bool => 1,
+        Ty::I16 => 2,
+        Ty::I32 | Ty::F32 => 4,
+        Ty::I64 | Ty::F64 => 8,
+        Ty::Ptr | Ty::Ref(_) => pointer_bytes,
+    }
+}
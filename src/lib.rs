@@ -1,2 +1,7 @@
+pub mod call;
+pub mod error;
 pub mod frontend;
 pub mod jit;
+pub mod layout;
+pub mod runtime;
+pub mod typeck;
@@ -0,0 +1,214 @@
+//! Support routines which JIT-compiled code calls back into.
+//!
+//! Rather than letting the processor trap on things like a division by zero,
+//! which would kill the host process, the generated code checks for them
+//! itself and reports them here. The JIT'd function which hit the error then
+//! returns straight away, as does each JIT'd function on the way back out to
+//! the host, so nothing runs after the fault, and the host can check for an
+//! error once the call into JIT'd code returns.
+
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::mem;
+
+/// The errors which can be raised while running JIT-compiled code. The
+/// values which the functions return after a trap are unspecified.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Trap {
+    /// An integer division or remainder by zero.
+    DivisionByZero,
+
+    /// The signed division `i64::MIN / -1`, whose result doesn't fit in an
+    /// `i64`.
+    DivisionOverflow,
+
+    /// An index into a local array which is past its end.
+    IndexOutOfBounds,
+
+    /// A load or store of `width` bytes at `offset` bytes into the data
+    /// object `symbol` runs past its end.
+    OutOfBounds {
+        symbol: String,
+        offset: usize,
+        width: usize,
+    },
+
+    /// A load or store of `width` bytes at an address which isn't in any
+    /// data object.
+    InvalidAddress { address: usize, width: usize },
+
+    /// A store at `offset` bytes into the read-only data object `symbol`,
+    /// such as a string literal.
+    ReadOnly { symbol: String, offset: usize },
+}
+
+impl Trap {
+    /// The code which JIT-compiled code passes to `TRAP_SYMBOL` to report
+    /// this trap.
+    pub(crate) fn code(&self) -> i64 {
+        match self {
+            Trap::DivisionByZero => 0,
+            Trap::DivisionOverflow => 1,
+            Trap::IndexOutOfBounds => 2,
+            Trap::OutOfBounds { .. } | Trap::InvalidAddress { .. } | Trap::ReadOnly { .. } => {
+                unreachable!("memory traps are raised by `check_access` itself")
+            }
+        }
+    }
+
+    fn from_code(code: i64) -> Trap {
+        match code {
+            0 => Trap::DivisionByZero,
+            1 => Trap::DivisionOverflow,
+            2 => Trap::IndexOutOfBounds,
+            _ => unreachable!("unknown trap code {code}"),
+        }
+    }
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Trap::DivisionByZero => write!(f, "attempt to divide by zero"),
+            Trap::DivisionOverflow => write!(f, "attempt to divide with overflow"),
+            Trap::IndexOutOfBounds => write!(f, "index out of bounds of a local array"),
+            Trap::OutOfBounds {
+                symbol,
+                offset,
+                width,
+            } => write!(
+                f,
+                "out of bounds access of {width} byte(s) at offset {offset} of `{symbol}`"
+            ),
+            Trap::InvalidAddress { address, width } => write!(
+                f,
+                "access of {width} byte(s) at {address:#x}, which isn't in any data object"
+            ),
+            Trap::ReadOnly { symbol, offset } => {
+                write!(f, "write at offset {offset} of read-only `{symbol}`")
+            }
+        }
+    }
+}
+
+/// The state shared between the JIT and the code it generates. The address
+/// of this is baked into the generated code, so it must not move while that
+/// code is alive.
+#[derive(Default)]
+pub(crate) struct Runtime {
+    /// The first trap raised since the last call to `take_trap`.
+    trap: Cell<Option<Trap>>,
+
+    /// Whether `trap` holds a trap, which the generated code reads after
+    /// each call to find out whether it has to return straight away.
+    trapped: Cell<bool>,
+
+    /// The data objects which memory accesses are checked against when
+    /// bounds checks are enabled.
+    data_objects: RefCell<Vec<DataObject>>,
+}
+
+struct DataObject {
+    symbol: String,
+    start: usize,
+    len: usize,
+    writable: bool,
+}
+
+impl Runtime {
+    pub(crate) fn take_trap(&self) -> Option<Trap> {
+        self.trapped.set(false);
+        self.trap.take()
+    }
+
+    /// The offset of the flag which is set while there's a trap, as a byte
+    /// which the generated code can load.
+    pub(crate) fn trapped_offset() -> i32 {
+        mem::offset_of!(Runtime, trapped) as i32
+    }
+
+    /// Record the location of a data object, so that accesses to it can be
+    /// checked.
+    pub(crate) fn register_data(&self, symbol: &str, start: *const u8, len: usize, writable: bool) {
+        self.data_objects.borrow_mut().push(DataObject {
+            symbol: symbol.to_owned(),
+            start: start as usize,
+            len,
+            writable,
+        });
+    }
+
+    fn raise(&self, trap: Trap) {
+        // Keep the first trap, since later ones are usually a consequence of
+        // it.
+        let first = self.trap.take().unwrap_or(trap);
+        self.trap.set(Some(first));
+        self.trapped.set(true);
+    }
+}
+
+/// Whether `name` is reserved for the routines here, so that scripts can't
+/// define or call functions with names starting with `__toy_`.
+pub(crate) fn is_reserved(name: &str) -> bool {
+    name.starts_with("__toy_")
+}
+
+/// The name under which `trap` is made available to JIT-compiled code.
+pub(crate) const TRAP_SYMBOL: &str = "__toy_trap";
+
+/// Called from JIT-compiled code to report a trap.
+pub(crate) extern "C" fn trap(runtime: *const Runtime, code: i64) {
+    // Safety: the generated code always passes the address of the `Runtime`
+    // owned by the JIT which compiled it.
+    let runtime = unsafe { &*runtime };
+    runtime.raise(Trap::from_code(code));
+}
+
+/// The name under which `check_access` is made available to JIT-compiled
+/// code.
+pub(crate) const CHECK_ACCESS_SYMBOL: &str = "__toy_check_access";
+
+/// Called from JIT-compiled code before each load or store of `width` bytes
+/// at `address` when bounds checks are enabled; `store` is non-zero for a
+/// store. Returns `address` if the access lies within a data object which
+/// allows it. Otherwise, this reports a trap and returns null, so that the
+/// generated code can return instead of making the access.
+pub(crate) extern "C" fn check_access(
+    runtime: *const Runtime,
+    address: *mut u8,
+    width: usize,
+    store: usize,
+) -> *mut u8 {
+    // Safety: as for `trap`.
+    let runtime = unsafe { &*runtime };
+
+    // Attribute the access to the last object which starts at or before
+    // it, since that's the one an index which runs off the end of an object
+    // most likely started from.
+    let addr = address as usize;
+    let trap = {
+        let objects = runtime.data_objects.borrow();
+        let object = objects
+            .iter()
+            .filter(|object| object.start <= addr)
+            .max_by_key(|object| object.start);
+        match object {
+            Some(object) if addr - object.start + width > object.len => Trap::OutOfBounds {
+                symbol: object.symbol.clone(),
+                offset: addr - object.start,
+                width,
+            },
+            Some(object) if store != 0 && !object.writable => Trap::ReadOnly {
+                symbol: object.symbol.clone(),
+                offset: addr - object.start,
+            },
+            Some(_) => return address,
+            None => Trap::InvalidAddress {
+                address: addr,
+                width,
+            },
+        }
+    };
+    runtime.raise(trap);
+    std::ptr::null_mut()
+}
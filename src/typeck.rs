@@ -0,0 +1,1094 @@
+//! Type checking, which runs over each function before it's translated.
+//!
+//! This works out the type of every variable, checks that each expression
+//! is applied to values of the right types, and records the type of each
+//! expression in its `ty` field for the translation to use. A variable which
+//! isn't annotated with a type takes the type of the first value assigned to
+//! it, and a literal takes whichever type the context calls for, or `i64`
+//! or `f64` if the context doesn't say.
+//!
+//! A name which isn't a parameter or return variable refers to the global or
+//! constant of that name, if there is one, rather than to a local variable.
+//!
+//! Everything about the source which can be wrong is caught here, such as
+//! literals which don't fit in their types, so that translation can't fail
+//! once the functions have been declared to the module.
+
+use crate::error::JitError;
+use crate::frontend::*;
+use crate::layout::{self, StructLayout};
+use crate::runtime;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// The types of a function's parameters and return values.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FunctionType {
+    pub params: Vec<Ty>,
+    pub returns: Vec<Ty>,
+}
+
+impl FunctionType {
+    pub fn of(function: &Function) -> FunctionType {
+        FunctionType {
+            params: function.params.iter().map(|(_, ty)| *ty).collect(),
+            returns: function.returns.iter().map(|(_, ty)| *ty).collect(),
+        }
+    }
+
+    pub fn of_extern(decl: &Extern) -> FunctionType {
+        FunctionType {
+            params: decl.params.iter().map(|(_, ty)| *ty).collect(),
+            returns: decl.returns.iter().map(|(_, ty)| *ty).collect(),
+        }
+    }
+}
+
+impl fmt::Display for FunctionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |types: &[Ty]| {
+            types
+                .iter()
+                .map(Ty::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        write!(f, "fn({}) -> ({})", join(&self.params), join(&self.returns))
+    }
+}
+
+/// A call to a function which the JIT doesn't define and which wasn't
+/// declared with `extern fn`, which the host process has to provide. Its
+/// parameter types are those of the arguments of the first call to it, and it
+/// is assumed to return a single `i64`.
+#[derive(Clone, Debug)]
+pub struct Import {
+    pub name: String,
+    pub ty: FunctionType,
+    pub span: Span,
+}
+
+/// The definition of a global variable or constant.
+#[derive(Clone, Debug)]
+pub struct GlobalDef {
+    pub ty: Ty,
+
+    /// The value of a constant, encoded as it's stored in memory, or `None`
+    /// for a global variable.
+    pub constant: Option<Vec<u8>>,
+}
+
+/// Type check `function`, given the types of the functions it may call, the
+/// layouts of the structs and the globals it may use, the names of the data
+/// objects whose addresses it may take, and the width of pointers. Calls to
+/// any other functions are added to `imports`, unless they're there already.
+/// Returns the names and types of all of the function's variables, in the
+/// order in which they're declared.
+pub fn check_function(
+    function: &mut Function,
+    functions: &HashMap<String, FunctionType>,
+    imports: &mut Vec<Import>,
+    structs: &HashMap<String, StructLayout>,
+    globals: &HashMap<String, GlobalDef>,
+    data: &HashSet<String>,
+    pointer_bytes: u32,
+) -> Result<Vec<(String, Ty)>, JitError> {
+    let mut checker = TypeChecker {
+        functions,
+        imports,
+        structs,
+        globals,
+        data,
+        pointer_bytes,
+        variables: HashMap::new(),
+        order: Vec::new(),
+        assigned: HashSet::new(),
+        arrays: HashMap::new(),
+        depth: 0,
+        loops: Vec::new(),
+        returns: function.returns.iter().map(|(_, ty)| *ty).collect(),
+    };
+    for (name, ty) in function.params.iter().chain(&function.returns) {
+        checker.declare(name, *ty);
+    }
+
+    // Variables are declared implicitly by assigning to them, and may be
+    // read before the first assignment in the source, for example in a
+    // loop, so find all of them, and all of their annotations, first.
+    for stmt in &function.stmts {
+        checker.collect_variables(stmt)?;
+    }
+
+    for stmt in &mut function.stmts {
+        checker.check(stmt, None)?;
+    }
+
+    let TypeChecker {
+        mut variables,
+        order,
+        ..
+    } = checker;
+    Ok(order
+        .into_iter()
+        .map(|name| {
+            let ty = variables.remove(&name).unwrap();
+            (name, ty)
+        })
+        .collect())
+}
+
+struct TypeChecker<'a> {
+    functions: &'a HashMap<String, FunctionType>,
+    imports: &'a mut Vec<Import>,
+    structs: &'a HashMap<String, StructLayout>,
+    globals: &'a HashMap<String, GlobalDef>,
+    data: &'a HashSet<String>,
+    pointer_bytes: u32,
+
+    /// The variables whose types are known so far.
+    variables: HashMap<String, Ty>,
+
+    /// The names of the variables in `variables`, in the order in which
+    /// their types became known.
+    order: Vec<String>,
+
+    /// The names of all variables which are assigned to somewhere in the
+    /// function.
+    assigned: HashSet<String>,
+
+    /// The element types of the local arrays declared so far. An array
+    /// must be declared before it's used.
+    arrays: HashMap<String, Ty>,
+
+    /// How many expressions enclose the one being checked, which is 0 for
+    /// the statements at the top level of the function body.
+    depth: usize,
+
+    /// The labels of the loops enclosing the code being checked, innermost
+    /// last.
+    loops: Vec<Option<String>>,
+
+    returns: Vec<Ty>,
+}
+
+impl TypeChecker<'_> {
+    /// The global which `name` refers to, unless it's shadowed by a
+    /// parameter or return variable.
+    fn global(&self, name: &str) -> Option<&GlobalDef> {
+        match self.variables.get(name) {
+            Some(_) => None,
+            None => self.globals.get(name),
+        }
+    }
+
+    fn declare(&mut self, name: &str, ty: Ty) {
+        self.variables.insert(name.to_owned(), ty);
+        self.order.push(name.to_owned());
+    }
+
+    /// Recursively descend through the AST, finding all of the variables
+    /// which are assigned to and the types of those which are annotated.
+    fn collect_variables(&mut self, expr: &Expr) -> Result<(), JitError> {
+        match &expr.kind {
+            ExprKind::Assign(name, _, _) if self.global(name).is_some() => {}
+            ExprKind::Assign(name, annotation, _) => {
+                self.assigned.insert(name.clone());
+                if let Some(ty) = *annotation {
+                    match self.variables.get(name) {
+                        Some(&found) if found != ty => {
+                            return Err(JitError::TypeMismatch {
+                                expected: found,
+                                found: ty,
+                                span: expr.span,
+                            });
+                        }
+                        Some(_) => {}
+                        None => self.declare(name, ty),
+                    }
+                }
+            }
+            ExprKind::TupleAssign(names, _) => {
+                for name in names {
+                    if self.global(name).is_none() {
+                        self.assigned.insert(name.clone());
+                    }
+                }
+            }
+            ExprKind::IfElse(branches, else_body) => {
+                for (_condition, then_body) in branches {
+                    for stmt in then_body {
+                        self.collect_variables(stmt)?;
+                    }
+                }
+                for stmt in else_body {
+                    self.collect_variables(stmt)?;
+                }
+            }
+            ExprKind::Match(_, arms) => {
+                for arm in arms {
+                    for stmt in &arm.body {
+                        self.collect_variables(stmt)?;
+                    }
+                }
+            }
+            ExprKind::WhileLoop(_, _condition, loop_body) => {
+                for stmt in loop_body {
+                    self.collect_variables(stmt)?;
+                }
+            }
+            ExprKind::ForLoop { var, body, .. } => {
+                self.assigned.insert(var.clone());
+                for stmt in body {
+                    self.collect_variables(stmt)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Check `expr`, record its type, and return it. `hint` is the type
+    /// which the context expects, if it expects a particular one, which is
+    /// used to give literals a type; the caller is responsible for
+    /// checking that the result actually has that type.
+    fn check(&mut self, expr: &mut Expr, hint: Option<Ty>) -> Result<Option<Ty>, JitError> {
+        let span = expr.span;
+        let depth = self.depth;
+        self.depth += 1;
+        let ty = match &mut expr.kind {
+            ExprKind::Literal(literal) => {
+                let ty = literal_type(hint);
+                self.check_literal(literal, false, ty, span)?;
+                Some(ty)
+            }
+
+            ExprKind::Float(literal) => {
+                let ty = match hint {
+                    Some(Ty::F32) => Ty::F32,
+                    _ => Ty::F64,
+                };
+                // Literals too large for the type would become infinite.
+                let text = literal.replace('_', "");
+                let finite = match ty {
+                    Ty::F32 => text.parse::<f32>().is_ok_and(f32::is_finite),
+                    _ => text.parse::<f64>().is_ok_and(f64::is_finite),
+                };
+                if !finite {
+                    return Err(JitError::LiteralOutOfRange {
+                        literal: literal.clone(),
+                        span,
+                    });
+                }
+                Some(ty)
+            }
+
+            ExprKind::Bool(_) => Some(Ty::Bool),
+
+            ExprKind::Str(_) => Some(Ty::Ptr),
+
+            ExprKind::Identifier(name) => Some(self.variable(name, span)?),
+
+            ExprKind::Assign(name, annotation, value) => {
+                let ty = match self.variables.get(name) {
+                    Some(&ty) => {
+                        self.expect(value, ty)?;
+                        ty
+                    }
+                    None if self.global(name).is_some() => {
+                        let ty = self.assign_global(name, span)?;
+                        if let Some(annotation) = *annotation
+                            && annotation != ty
+                        {
+                            return Err(JitError::TypeMismatch {
+                                expected: ty,
+                                found: annotation,
+                                span,
+                            });
+                        }
+                        self.expect(value, ty)?;
+                        ty
+                    }
+                    None => {
+                        let ty = self.check_value(value, None)?;
+                        self.declare(name, ty);
+                        ty
+                    }
+                };
+                Some(ty)
+            }
+
+            ExprKind::TupleAssign(names, value) => {
+                let types = match &mut value.kind {
+                    ExprKind::Call(callee, args) => {
+                        let types = self.check_call(callee, args, value.span)?;
+                        value.ty = types.first().copied();
+                        types
+                    }
+                    _ => vec![self.check_value(value, None)?],
+                };
+                if types.len() != names.len() {
+                    return Err(JitError::ValueCountMismatch {
+                        expected: names.len(),
+                        found: types.len(),
+                        span,
+                    });
+                }
+                for (name, &ty) in names.iter().zip(&types) {
+                    let expected = match self.variables.get(name) {
+                        Some(&expected) => Some(expected),
+                        None if self.global(name).is_some() => {
+                            Some(self.assign_global(name, span)?)
+                        }
+                        None => None,
+                    };
+                    match expected {
+                        Some(expected) if expected != ty => {
+                            return Err(JitError::TypeMismatch {
+                                expected,
+                                found: ty,
+                                span,
+                            });
+                        }
+                        Some(_) => {}
+                        None => self.declare(name, ty),
+                    }
+                }
+                None
+            }
+
+            ExprKind::ArrayDecl(name, ty, len) => {
+                if depth > 0 {
+                    return Err(JitError::NestedArray { span });
+                }
+                let defined = self.variables.contains_key(name)
+                    || self.assigned.contains(name)
+                    || self.globals.contains_key(name)
+                    || self.arrays.contains_key(name);
+                if defined {
+                    return Err(JitError::Redefinition {
+                        name: name.clone(),
+                        span,
+                    });
+                }
+                if len
+                    .checked_mul(layout::size_of(*ty, self.pointer_bytes))
+                    .is_none()
+                {
+                    return Err(JitError::LiteralOutOfRange {
+                        literal: len.to_string(),
+                        span,
+                    });
+                }
+                self.arrays.insert(name.clone(), *ty);
+                None
+            }
+
+            ExprKind::Index(name, index) => {
+                let ty = self.array(name, span)?;
+                self.expect(index, Ty::I64)?;
+                Some(ty)
+            }
+
+            ExprKind::IndexAssign(name, index, value) => {
+                let ty = self.array(name, span)?;
+                self.expect(index, Ty::I64)?;
+                self.expect(value, ty)?;
+                Some(ty)
+            }
+
+            ExprKind::Eq(lhs, rhs) => self.check_equality("`==`", lhs, rhs, span)?,
+            ExprKind::Ne(lhs, rhs) => self.check_equality("`!=`", lhs, rhs, span)?,
+
+            ExprKind::Lt(lhs, rhs)
+            | ExprKind::Le(lhs, rhs)
+            | ExprKind::Gt(lhs, rhs)
+            | ExprKind::Ge(lhs, rhs) => {
+                let ty = self.check_operands(lhs, rhs, None)?;
+                let ok = ty.is_integer() || ty.is_float() || ty.is_pointer();
+                require("comparison", ty, span, ok)?;
+                Some(Ty::Bool)
+            }
+
+            ExprKind::ULt(lhs, rhs)
+            | ExprKind::ULe(lhs, rhs)
+            | ExprKind::UGt(lhs, rhs)
+            | ExprKind::UGe(lhs, rhs) => {
+                let ty = self.check_operands(lhs, rhs, None)?;
+                require(
+                    "unsigned comparison",
+                    ty,
+                    span,
+                    ty.is_integer() || ty.is_pointer(),
+                )?;
+                Some(Ty::Bool)
+            }
+
+            ExprKind::Add(lhs, rhs) | ExprKind::Sub(lhs, rhs) => {
+                // A pointer can be offset by adding or subtracting a number
+                // of bytes.
+                let ty = if is_literal(lhs) && !is_literal(rhs) {
+                    self.check_operands(lhs, rhs, hint)?
+                } else {
+                    let ty = self.check_value(lhs, hint)?;
+                    self.expect(rhs, if ty == Ty::Ptr { Ty::I64 } else { ty })?;
+                    ty
+                };
+                let ok = ty.is_integer() || ty.is_float() || ty == Ty::Ptr;
+                require("arithmetic", ty, span, ok)?;
+                Some(ty)
+            }
+
+            ExprKind::Mul(lhs, rhs) | ExprKind::Div(lhs, rhs) => {
+                let ty = self.check_operands(lhs, rhs, hint)?;
+                require("arithmetic", ty, span, ty.is_integer() || ty.is_float())?;
+                Some(ty)
+            }
+
+            // Cranelift has no instruction for the remainder of floats.
+            ExprKind::Rem(lhs, rhs) | ExprKind::UDiv(lhs, rhs) | ExprKind::URem(lhs, rhs) => {
+                let ty = self.check_operands(lhs, rhs, hint)?;
+                require("arithmetic", ty, span, ty.is_integer())?;
+                Some(ty)
+            }
+
+            ExprKind::BitAnd(lhs, rhs) | ExprKind::BitOr(lhs, rhs) | ExprKind::BitXor(lhs, rhs) => {
+                let ty = self.check_operands(lhs, rhs, hint)?;
+                require(
+                    "bitwise operation",
+                    ty,
+                    span,
+                    ty.is_integer() || ty == Ty::Bool,
+                )?;
+                Some(ty)
+            }
+
+            // The shift amount may have a different type from the value
+            // being shifted.
+            ExprKind::Shl(lhs, rhs) | ExprKind::Shr(lhs, rhs) | ExprKind::UShr(lhs, rhs) => {
+                let ty = self.check_value(lhs, hint)?;
+                require("shift", ty, span, ty.is_integer())?;
+                let amount = self.check_value(rhs, None)?;
+                require("shift", amount, rhs.span, amount.is_integer())?;
+                Some(ty)
+            }
+
+            ExprKind::Neg(operand) => {
+                // A negative literal is checked as a whole, since its
+                // magnitude may only fit when it's negative, as with `-128`
+                // as an `i8`.
+                let ty = match &operand.kind {
+                    ExprKind::Literal(literal) => {
+                        let ty = literal_type(hint);
+                        self.check_literal(literal, true, ty, span)?;
+                        operand.ty = Some(ty);
+                        ty
+                    }
+                    _ => self.check_value(operand, hint)?,
+                };
+                require("`-`", ty, span, ty.is_integer() || ty.is_float())?;
+                Some(ty)
+            }
+
+            ExprKind::BitNot(operand) => {
+                let ty = self.check_value(operand, hint)?;
+                require("`~`", ty, span, ty.is_integer())?;
+                Some(ty)
+            }
+
+            ExprKind::Not(operand) => {
+                self.check_condition(operand)?;
+                Some(Ty::Bool)
+            }
+
+            ExprKind::And(lhs, rhs) | ExprKind::Or(lhs, rhs) => {
+                self.check_condition(lhs)?;
+                self.check_condition(rhs)?;
+                Some(Ty::Bool)
+            }
+
+            ExprKind::Cast(operand, to) => {
+                let from = self.check_value(operand, None)?;
+                // Integers can be converted to and from anything, but floats
+                // can only be converted to and from integers and each other.
+                let integral = |ty: Ty| ty.is_integer() || ty == Ty::Bool || ty.is_pointer();
+                let numeric = |ty: Ty| ty.is_integer() || ty.is_float();
+                let castable = integral(from) && integral(*to) || numeric(from) && numeric(*to);
+                if !castable {
+                    return Err(JitError::InvalidCast {
+                        from,
+                        to: *to,
+                        span,
+                    });
+                }
+                Some(*to)
+            }
+
+            ExprKind::IfElse(branches, else_body) => {
+                let mut tails = Vec::new();
+                for (condition, then_body) in branches {
+                    self.check_condition(condition)?;
+                    tails.push(self.check_body(then_body, hint)?);
+                }
+                tails.push(self.check_body(else_body, hint)?);
+                common_type(tails)
+            }
+
+            ExprKind::Match(scrutinee, arms) => {
+                let ty = self.check_value(scrutinee, None)?;
+                require(
+                    "`match`",
+                    ty,
+                    scrutinee.span,
+                    ty.is_integer() || ty == Ty::Bool,
+                )?;
+
+                let mut tails = Vec::new();
+                let mut exhaustive = false;
+                let mut values = HashSet::new();
+                for arm in arms {
+                    for pattern in &mut arm.patterns {
+                        // Nothing gets past a wildcard, or a value which an
+                        // earlier pattern matches.
+                        if exhaustive {
+                            return Err(JitError::UnreachablePattern {
+                                span: pattern.span(),
+                            });
+                        }
+                        match pattern {
+                            Pattern::Wildcard(_) => exhaustive = true,
+                            Pattern::Value(value) => {
+                                if !self.is_constant(value) {
+                                    return Err(JitError::NotConstant { span: value.span });
+                                }
+                                self.expect(value, ty)?;
+                                if !values.insert(self.pattern_value(value, ty)) {
+                                    return Err(JitError::UnreachablePattern { span: value.span });
+                                }
+                            }
+                        }
+                    }
+                    tails.push(self.check_body(&mut arm.body, hint)?);
+                }
+
+                // A match without a wildcard behaves as though it ended with
+                // one whose body is empty.
+                if !exhaustive {
+                    tails.push(None);
+                }
+                common_type(tails)
+            }
+
+            ExprKind::WhileLoop(label, condition, loop_body) => {
+                // The condition is inside the loop, since it's evaluated on
+                // every iteration.
+                self.loops.push(label.clone());
+                self.check_condition(condition)?;
+                self.check_body(loop_body, None)?;
+                self.loops.pop();
+                None
+            }
+
+            ExprKind::ForLoop {
+                label,
+                var,
+                start,
+                end,
+                step,
+                body,
+                ..
+            } => {
+                // The loop variable is always local, so it can't share its
+                // name with a global, which other uses of the name would
+                // refer to.
+                if self.global(var).is_some() {
+                    return Err(JitError::Redefinition {
+                        name: var.clone(),
+                        span,
+                    });
+                }
+                let ty = match self.variables.get(var) {
+                    Some(&ty) => {
+                        self.expect(start, ty)?;
+                        self.expect(end, ty)?;
+                        ty
+                    }
+                    None => {
+                        let ty = self.check_operands(start, end, None)?;
+                        self.declare(var, ty);
+                        ty
+                    }
+                };
+                require("range", ty, span, ty.is_integer())?;
+                if let Some(step) = step {
+                    self.expect(step, ty)?;
+                    // A step which isn't positive only turns up at runtime
+                    // if it isn't a literal, in which case the loop runs no
+                    // iterations.
+                    if is_non_positive_literal(step) {
+                        return Err(JitError::InvalidStep { span: step.span });
+                    }
+                }
+                self.loops.push(label.clone());
+                self.check_body(body, None)?;
+                self.loops.pop();
+                None
+            }
+
+            ExprKind::Break(label) | ExprKind::Continue(label) => {
+                let found = match label {
+                    Some(label) => self.loops.contains(&Some(label.clone())),
+                    None => !self.loops.is_empty(),
+                };
+                if !found {
+                    return Err(match label {
+                        Some(label) => JitError::UndefinedLabel {
+                            label: label.clone(),
+                            span,
+                        },
+                        None => JitError::BreakOutsideLoop { span },
+                    });
+                }
+                None
+            }
+
+            ExprKind::Return(values) => {
+                if !values.is_empty() {
+                    if values.len() != self.returns.len() {
+                        return Err(JitError::ValueCountMismatch {
+                            expected: self.returns.len(),
+                            found: values.len(),
+                            span,
+                        });
+                    }
+                    for (value, ty) in values.iter_mut().zip(self.returns.clone()) {
+                        self.expect(value, ty)?;
+                    }
+                }
+                None
+            }
+
+            // When a call is used as a value, its value is the first value
+            // returned by the callee.
+            ExprKind::Call(name, args) => self.check_call(name, args, span)?.first().copied(),
+
+            // Only global variables and data objects live in memory; a
+            // constant is replaced by its value wherever it's used.
+            ExprKind::GlobalDataAddr(name) => {
+                match self.globals.get(name) {
+                    Some(global) if global.constant.is_some() => {
+                        return Err(JitError::AddressOfConstant {
+                            name: name.clone(),
+                            span,
+                        });
+                    }
+                    Some(_) => {}
+                    None if self.data.contains(name) => {}
+                    None => {
+                        return Err(JitError::UndefinedVariable {
+                            name: name.clone(),
+                            span,
+                        });
+                    }
+                }
+                Some(Ty::Ptr)
+            }
+
+            ExprKind::Field(object, field) => Some(self.check_field(object, field, span)?),
+
+            ExprKind::FieldAssign(object, field, value) => {
+                let ty = self.check_field(object, field, span)?;
+                self.expect(value, ty)?;
+                Some(ty)
+            }
+
+            ExprKind::Load(ty, address) => {
+                self.expect(address, Ty::Ptr)?;
+                Some(*ty)
+            }
+
+            ExprKind::Store(ty, address, value) => {
+                self.expect(address, Ty::Ptr)?;
+                self.expect(value, *ty)?;
+                None
+            }
+        };
+        self.depth = depth;
+        expr.ty = ty;
+        Ok(ty)
+    }
+
+    /// Check an expression which must produce a value.
+    fn check_value(&mut self, expr: &mut Expr, hint: Option<Ty>) -> Result<Ty, JitError> {
+        match self.check(expr, hint)? {
+            Some(ty) => Ok(ty),
+            None => Err(JitError::NoValue { span: expr.span }),
+        }
+    }
+
+    /// Check an expression which must produce a value of type `ty`.
+    fn expect(&mut self, expr: &mut Expr, ty: Ty) -> Result<(), JitError> {
+        let found = self.check_value(expr, Some(ty))?;
+        if found != ty {
+            return Err(JitError::TypeMismatch {
+                expected: ty,
+                found,
+                span: expr.span,
+            });
+        }
+        Ok(())
+    }
+
+    /// Check the operands of a binary operator, which must have the same
+    /// type, and return that type. If only the left-hand side is a literal,
+    /// its type comes from the right-hand side, so that `1 + x` works the
+    /// same way as `x + 1`.
+    fn check_operands(
+        &mut self,
+        lhs: &mut Expr,
+        rhs: &mut Expr,
+        hint: Option<Ty>,
+    ) -> Result<Ty, JitError> {
+        if is_literal(lhs) && !is_literal(rhs) {
+            let ty = self.check_value(rhs, hint)?;
+            self.expect(lhs, ty)?;
+            Ok(ty)
+        } else {
+            let ty = self.check_value(lhs, hint)?;
+            self.expect(rhs, ty)?;
+            Ok(ty)
+        }
+    }
+
+    /// Check the operands of `==` or `!=`.
+    fn check_equality(
+        &mut self,
+        op: &str,
+        lhs: &mut Expr,
+        rhs: &mut Expr,
+        span: Span,
+    ) -> Result<Option<Ty>, JitError> {
+        let ty = self.check_operands(lhs, rhs, None)?;
+        let ok = ty.is_integer() || ty.is_float() || ty == Ty::Bool || ty.is_pointer();
+        require(op, ty, span, ok)?;
+        Ok(Some(Ty::Bool))
+    }
+
+    /// Check the condition of an `if` or loop, or an operand of a logical
+    /// operator. Besides a `bool`, any integer or pointer can be used as a
+    /// condition, which is true if it isn't zero.
+    fn check_condition(&mut self, expr: &mut Expr) -> Result<(), JitError> {
+        let ty = self.check_value(expr, Some(Ty::Bool))?;
+        if !(ty == Ty::Bool || ty.is_integer() || ty.is_pointer()) {
+            return Err(JitError::TypeMismatch {
+                expected: Ty::Bool,
+                found: ty,
+                span: expr.span,
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether `expr` is a literal, or the name of a constant, which can be
+    /// used as a pattern.
+    fn is_constant(&self, expr: &Expr) -> bool {
+        match &expr.kind {
+            ExprKind::Bool(_) => true,
+            ExprKind::Identifier(name) => self
+                .global(name)
+                .is_some_and(|global| global.constant.is_some()),
+            _ => is_literal(expr),
+        }
+    }
+
+    /// Check the statements of a block, passing `hint` to the last of them.
+    /// Returns `None` if the block is empty, or else the type of the value
+    /// of its last statement.
+    fn check_body(
+        &mut self,
+        body: &mut [Expr],
+        hint: Option<Ty>,
+    ) -> Result<Option<Option<Ty>>, JitError> {
+        let Some((last, rest)) = body.split_last_mut() else {
+            return Ok(None);
+        };
+        for stmt in rest {
+            self.check(stmt, None)?;
+        }
+        Ok(Some(self.check(last, hint)?))
+    }
+
+    /// Check a call, and return the types of the values it returns.
+    fn check_call(
+        &mut self,
+        name: &str,
+        args: &mut [Expr],
+        span: Span,
+    ) -> Result<Vec<Ty>, JitError> {
+        if runtime::is_reserved(name) {
+            return Err(JitError::ReservedName {
+                name: name.to_owned(),
+                span,
+            });
+        }
+        let known = self.functions.get(name).or_else(|| {
+            let import = self.imports.iter().find(|import| import.name == name);
+            import.map(|import| &import.ty)
+        });
+        let Some(function) = known else {
+            // This is most likely an external function which hasn't been
+            // declared with `extern fn`, whose type we can't know, so take
+            // the types of the arguments as those of its parameters, and
+            // assume it returns a single `i64`. Later calls have to agree.
+            let mut params = Vec::new();
+            for arg in args {
+                params.push(self.check_value(arg, None)?);
+            }
+            self.imports.push(Import {
+                name: name.to_owned(),
+                ty: FunctionType {
+                    params,
+                    returns: vec![Ty::I64],
+                },
+                span,
+            });
+            return Ok(vec![Ty::I64]);
+        };
+
+        if function.params.len() != args.len() {
+            return Err(JitError::ArityMismatch {
+                name: name.to_owned(),
+                expected: function.params.len(),
+                found: args.len(),
+                span,
+            });
+        }
+        let function = function.clone();
+        for (arg, &ty) in args.iter_mut().zip(&function.params) {
+            self.expect(arg, ty)?;
+        }
+        Ok(function.returns)
+    }
+
+    /// Check an access to the field `field` of the struct which `object`
+    /// points to, and return the field's type.
+    fn check_field(&mut self, object: &mut Expr, field: &str, span: Span) -> Result<Ty, JitError> {
+        let ty = self.check_value(object, None)?;
+        let Ty::Ref(name) = ty else {
+            return Err(JitError::InvalidOperand {
+                op: "field access".to_owned(),
+                ty,
+                span,
+            });
+        };
+        let Some(layout) = self.structs.get(name) else {
+            return Err(JitError::UndefinedStruct {
+                name: name.to_owned(),
+                span,
+            });
+        };
+        match layout.field(field) {
+            Some(field) => Ok(field.ty),
+            None => Err(JitError::UndefinedField {
+                name: name.to_owned(),
+                field: field.to_owned(),
+                span,
+            }),
+        }
+    }
+
+    /// Check that the integer literal `literal`, which is negated if
+    /// `negate` is set, fits in `ty`.
+    fn check_literal(
+        &self,
+        literal: &str,
+        negate: bool,
+        ty: Ty,
+        span: Span,
+    ) -> Result<(), JitError> {
+        let bits = layout::size_of(ty, self.pointer_bytes) * 8;
+        if parse_literal(literal, negate, bits).is_some() {
+            return Ok(());
+        }
+        let literal = if negate {
+            format!("-{literal}")
+        } else {
+            literal.to_owned()
+        };
+        Err(JitError::LiteralOutOfRange { literal, span })
+    }
+
+    /// The value which the constant `expr` of type `ty`, a pattern of a
+    /// match, matches, zero-extended from the width of `ty`.
+    fn pattern_value(&self, expr: &Expr, ty: Ty) -> u64 {
+        let bits = layout::size_of(ty, self.pointer_bytes) * 8;
+        let value = match &expr.kind {
+            ExprKind::Bool(value) => i64::from(*value),
+            ExprKind::Identifier(name) => {
+                let bytes = self.globals[name].constant.as_ref().unwrap();
+                let mut value = [0; 8];
+                value[..bytes.len()].copy_from_slice(bytes);
+                i64::from_le_bytes(value)
+            }
+            ExprKind::Neg(operand) => match &operand.kind {
+                ExprKind::Literal(literal) => parse_literal(literal, true, bits).unwrap(),
+                _ => unreachable!("patterns are constants"),
+            },
+            ExprKind::Literal(literal) => parse_literal(literal, false, bits).unwrap(),
+            _ => unreachable!("patterns are constants"),
+        };
+        match bits {
+            64 => value as u64,
+            bits => value as u64 & ((1 << bits) - 1),
+        }
+    }
+
+    /// The element type of the array `name`.
+    fn array(&self, name: &str, span: Span) -> Result<Ty, JitError> {
+        if let Some(&ty) = self.arrays.get(name) {
+            return Ok(ty);
+        }
+        match self.variables.get(name) {
+            Some(&ty) => Err(JitError::InvalidOperand {
+                op: "indexing".to_owned(),
+                ty,
+                span,
+            }),
+            None => Err(JitError::UndefinedVariable {
+                name: name.to_owned(),
+                span,
+            }),
+        }
+    }
+
+    /// Check that the global `name` can be assigned to, and return its type.
+    fn assign_global(&self, name: &str, span: Span) -> Result<Ty, JitError> {
+        let global = &self.globals[name];
+        if global.constant.is_some() {
+            return Err(JitError::AssignToConstant {
+                name: name.to_owned(),
+                span,
+            });
+        }
+        Ok(global.ty)
+    }
+
+    fn variable(&mut self, name: &str, span: Span) -> Result<Ty, JitError> {
+        if let Some(&ty) = self.variables.get(name) {
+            return Ok(ty);
+        }
+        if let Some(global) = self.globals.get(name) {
+            return Ok(global.ty);
+        }
+        if !self.assigned.contains(name) {
+            return Err(JitError::UndefinedVariable {
+                name: name.to_owned(),
+                span,
+            });
+        }
+
+        // The variable is read before the first assignment to it, so there's
+        // nothing to infer its type from.
+        self.declare(name, Ty::I64);
+        Ok(Ty::I64)
+    }
+}
+
+/// The type of the value of an if-else or match, given the results of
+/// `check_body` for each of its bodies. It produces a value if all of the
+/// bodies which aren't empty end with a value of the same type. An empty
+/// body produces zero.
+fn common_type(tails: Vec<Option<Option<Ty>>>) -> Option<Ty> {
+    let mut ty = None;
+    for tail in tails.into_iter().flatten() {
+        match (ty, tail) {
+            (_, None) => return None,
+            (None, Some(tail)) => ty = Some(tail),
+            (Some(ty), Some(tail)) if ty == tail => {}
+            (Some(_), Some(_)) => return None,
+        }
+    }
+    ty
+}
+
+/// The type of an integer literal, given the type which its context expects.
+fn literal_type(hint: Option<Ty>) -> Ty {
+    match hint {
+        Some(ty) if ty.is_integer() || ty.is_pointer() => ty,
+        _ => Ty::I64,
+    }
+}
+
+/// Whether `expr` is a numeric literal, possibly negated, whose type is
+/// determined by the context it's used in.
+fn is_literal(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Literal(_) | ExprKind::Float(_) => true,
+        ExprKind::Neg(operand) => is_literal(operand),
+        _ => false,
+    }
+}
+
+/// Whether `expr` is an integer literal, possibly negated, whose value is
+/// zero or negative.
+fn is_non_positive_literal(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Literal(literal) => {
+            let digits = match literal.get(..2) {
+                Some("0x" | "0o" | "0b") => &literal[2..],
+                _ => literal,
+            };
+            digits.chars().all(|c| c == '0' || c == '_')
+        }
+        ExprKind::Neg(operand) => matches!(operand.kind, ExprKind::Literal(_)),
+        _ => false,
+    }
+}
+
+/// Parse the text of an integer literal, which is negated if `negate` is
+/// set. Returns `None` if the value doesn't fit in a signed integer of
+/// `bits` bits.
+///
+/// Hexadecimal, octal and binary literals describe a bit pattern rather than
+/// a number, so they may use all of the bits; `0xff` is -1 as an `i8`.
+pub fn parse_literal(literal: &str, negate: bool, bits: u32) -> Option<i64> {
+    let (radix, digits) = match literal.get(..2) {
+        Some("0x") => (16, &literal[2..]),
+        Some("0o") => (8, &literal[2..]),
+        Some("0b") => (2, &literal[2..]),
+        _ => (10, literal),
+    };
+    let magnitude = u64::from_str_radix(&digits.replace('_', ""), radix).ok()?;
+    // The magnitude of the most negative value is one larger than that of
+    // the most positive value.
+    let min_magnitude = 1 << (bits - 1);
+    if negate {
+        if magnitude > min_magnitude {
+            return None;
+        }
+        Some((magnitude as i64).wrapping_neg())
+    } else if radix != 10 {
+        if bits < 64 && magnitude >> bits != 0 {
+            return None;
+        }
+        // Sign-extend the bit pattern.
+        let shift = 64 - bits;
+        Some(((magnitude << shift) as i64) >> shift)
+    } else if magnitude < min_magnitude {
+        Some(magnitude as i64)
+    } else {
+        None
+    }
+}
+
+/// Report that `op` can't be applied to a value of type `ty` unless `ok`.
+fn require(op: &str, ty: Ty, span: Span, ok: bool) -> Result<(), JitError> {
+    if ok {
+        Ok(())
+    } else {
+        Err(JitError::InvalidOperand {
+            op: op.to_owned(),
+            ty,
+            span,
+        })
+    }
+}
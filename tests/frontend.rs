@@ -0,0 +1,102 @@
+use cranelift_jit_demo::frontend::{ExprKind, parser};
+
+#[test]
+fn comments() {
+    let function = parser::function(
+        "// A leading comment.
+         fn f(a /* the input */, b) -> (r) { // After the signature.
+             /* Before a statement. */ r = a /* in an expression */ + b
+             /* Block comments /* can be nested */ and span
+                several lines. */
+             r = r * 2 // A trailing comment.
+             // A comment before the closing brace.
+         } /* After the function. */",
+    )
+    .unwrap();
+    assert_eq!(function.params.len(), 2);
+    assert_eq!(function.stmts.len(), 2);
+
+    // A comment marker inside a string is part of the string.
+    let function = parser::function(r#"fn f() -> (r) { r = "/* // */" }"#).unwrap();
+    let ExprKind::Assign(_, _, value) = &function.stmts[0].kind else {
+        panic!("expected an assignment");
+    };
+    assert!(matches!(&value.kind, ExprKind::Str(s) if s == "/* // */"));
+}
+
+#[test]
+fn unterminated_comments() {
+    assert!(parser::function("fn f() -> (r) { r = 1 /* }").is_err());
+    assert!(parser::function("fn f() -> (r) { r = 1 /* /* */ }").is_err());
+}
+
+#[test]
+fn one_line_function() {
+    let function = parser::function("fn f(a) -> (r) { r = a }").unwrap();
+    assert_eq!(function.stmts.len(), 1);
+
+    let function =
+        parser::function("fn f(a) -> (r) { r = a; r = r + 1;; if a { r = 0 } else { r = 1 }; }")
+            .unwrap();
+    assert_eq!(function.stmts.len(), 3);
+}
+
+#[test]
+fn free_placement_of_braces_and_newlines() {
+    let function = parser::function(
+        "fn f(
+             a,
+             b
+         ) -> (r)
+         {
+             r = if a
+             {
+                 1
+             }
+             else if b { 2 }
+             else
+             {
+                 3
+             }
+         }",
+    )
+    .unwrap();
+    assert_eq!(function.params.len(), 2);
+    assert_eq!(function.stmts.len(), 1);
+}
+
+#[test]
+fn crlf_line_endings() {
+    let function =
+        parser::function("fn f(a) -> (r) {\r\n    r = a // Comment.\r\n    r = r * 2\r\n}\r\n")
+            .unwrap();
+    assert_eq!(function.stmts.len(), 2);
+
+    let program = parser::program(
+        "struct S {\r\n    a: i8,\r\n}\r\n\r\nfn f() -> (r) {\r\n    r = 1\r\n}\r\n",
+    )
+    .unwrap();
+    assert_eq!(program.structs.len(), 1);
+    assert_eq!(program.functions.len(), 1);
+}
+
+#[test]
+fn trailing_commas() {
+    let function =
+        parser::function("fn f(a, b,) -> (r, s,) { r = g(a, b,); (r, s,) = h(a,) }").unwrap();
+    assert_eq!(function.params.len(), 2);
+    assert_eq!(function.returns.len(), 2);
+    let ExprKind::Assign(_, _, call) = &function.stmts[0].kind else {
+        panic!("expected an assignment");
+    };
+    assert!(matches!(&call.kind, ExprKind::Call(_, args) if args.len() == 2));
+
+    let program =
+        parser::program("struct S { a: i8, b: i16, }\nextern fn g(x: f64,) -> (r: f64,)").unwrap();
+    assert_eq!(program.structs[0].fields.len(), 2);
+    assert_eq!(program.externs[0].params.len(), 1);
+
+    // A comma on its own isn't a list.
+    assert!(parser::function("fn f(,) -> () {}").is_err());
+    assert!(parser::function("fn f(a,,) -> () {}").is_err());
+}
@@ -0,0 +1,366 @@
+use cranelift_jit_demo::error::JitError;
+use cranelift_jit_demo::jit::JIT;
+use cranelift_jit_demo::runtime::Trap;
+
+#[test]
+fn call_after_failed_compile() {
+    let mut jit = JIT::default();
+    assert!(jit.compile("fn f(a) -> (r) { r = nope }").is_err());
+    let result: Result<(i64,), _> = unsafe { jit.call("f", (1_i64,)) };
+    assert!(matches!(result, Err(JitError::UndefinedFunction { name, .. }) if name == "f"));
+}
+
+#[test]
+fn duplicate_function() {
+    let mut jit = JIT::default();
+    let result = jit.compile_program("fn f() -> (r) { r = 1 }\nfn f() -> (r) { r = 2 }");
+    assert!(matches!(result, Err(JitError::Redefinition { name, .. }) if name == "f"));
+
+    jit.compile("fn f() -> (r) { r = 3 }").unwrap();
+    let (r,): (i64,) = unsafe { jit.call("f", ()) }.unwrap();
+    assert_eq!(r, 3);
+}
+
+#[test]
+fn undefined_symbols() {
+    let mut jit = JIT::default();
+    let result = jit.compile("fn f(a) -> (r) { r = nosuchfn(a) }");
+    assert!(matches!(
+        result,
+        Err(JitError::UndefinedFunction { name, span: Some(_) }) if name == "nosuchfn"
+    ));
+    let result = jit.compile("fn g() -> (r) { r = load64(&typo) }");
+    assert!(matches!(result, Err(JitError::UndefinedVariable { name, .. }) if name == "typo"));
+
+    // An undeclared host function keeps the type of its first call.
+    jit.compile("fn p(a) -> (r) { r = labs(a) }").unwrap();
+    let result = jit.compile("fn q(a: f64) -> (r) { r = labs(a) }");
+    assert!(matches!(result, Err(JitError::TypeMismatch { .. })));
+}
+
+#[test]
+fn extern_functions() {
+    let mut jit = JIT::default();
+    let result = jit.compile_program("extern fn nosuchfn(x: f64) -> (r: f64)");
+    assert!(matches!(
+        result,
+        Err(JitError::UndefinedFunction { name, span: Some(_) }) if name == "nosuchfn"
+    ));
+    let result = jit.compile_program("extern fn atof(s: ptr) -> (r: f64)\nfn atof() -> (r) {}");
+    assert!(matches!(result, Err(JitError::Redefinition { name, .. }) if name == "atof"));
+
+    // The declared types are used for the call, so the result is a float.
+    jit.compile_program(
+        "extern fn atof(s: ptr) -> (r: f64)\nfn f(s: ptr) -> (r: f64) { r = atof(s) }",
+    )
+    .unwrap();
+    let (r,): (f64,) = unsafe { jit.call("f", (c"0.25".as_ptr(),)) }.unwrap();
+    assert_eq!(r, 0.25);
+    let result = jit.compile("fn g() -> (r) { r = atof(\"1\") }");
+    assert!(matches!(result, Err(JitError::TypeMismatch { .. })));
+    let result = jit.compile_program("extern fn atof(s: ptr) -> (r: f64)");
+    assert!(matches!(result, Err(JitError::Redefinition { name, .. }) if name == "atof"));
+}
+
+#[test]
+fn compile_after_failed_compile() {
+    let mut jit = JIT::default();
+
+    // Neither `f` nor `h` is defined, so calling them is an error.
+    assert!(jit.compile("fn f(a) -> (r) { r = nope }").is_err());
+    assert!(
+        jit.compile("fn h(a) -> (r) { r = 99999999999999999999 }")
+            .is_err()
+    );
+    for callee in ["f", "h"] {
+        let result = jit.compile(&format!("fn g(a) -> (r) {{ r = {callee}(a) }}"));
+        assert!(matches!(result, Err(JitError::UndefinedFunction { name, .. }) if name == callee));
+    }
+
+    jit.compile("fn f(a) -> (r) { r = a + 1 }").unwrap();
+    jit.compile("fn h(a) -> (r) { r = a * 2 }").unwrap();
+    jit.compile("fn g(a) -> (r) { r = h(f(a)) }").unwrap();
+    let (r,): (i64,) = unsafe { jit.call("g", (1_i64,)) }.unwrap();
+    assert_eq!(r, 4);
+}
+
+#[test]
+fn retry_with_different_signature() {
+    let mut jit = JIT::default();
+    let failures = [
+        "r = 99999999999999999999",
+        "x: i8 = -129",
+        "x: f32 = 1e39",
+        "let big[2000000000]: i64",
+        "match a { 1 => 2, 0x1 => 3 }",
+        "break",
+        "while a { continue 'outer }",
+        "r = nosuchfn(a)",
+    ];
+    for body in failures {
+        let result = jit.compile(&format!("fn h(a) -> (r) {{ {body} }}"));
+        assert!(result.is_err(), "`{body}` compiled");
+    }
+
+    // None of the failures left `h` declared with their signature.
+    jit.compile("fn h(a: f64) -> (r: f64) { r = a * 2.0 }")
+        .unwrap();
+    let (r,): (f64,) = unsafe { jit.call("h", (1.5,)) }.unwrap();
+    assert_eq!(r, 3.0);
+}
+
+#[test]
+fn address_of_constant() {
+    let mut jit = JIT::default();
+    let result = jit.compile_program("const LIMIT = 10\nfn f() -> (r: ptr) { r = &LIMIT }");
+    assert!(matches!(result, Err(JitError::AddressOfConstant { name, .. }) if name == "LIMIT"));
+}
+
+#[test]
+fn loop_variable_named_after_global() {
+    let mut jit = JIT::default();
+    let result = jit.compile_program(
+        "global g: i32 = 5\nfn f() -> (r) { x = g; for g in 0..3 {}; r = x as i64 }",
+    );
+    assert!(matches!(result, Err(JitError::Redefinition { name, .. }) if name == "g"));
+}
+
+#[test]
+fn global_named_after_data_object() {
+    let mut jit = JIT::default();
+    jit.create_data("g", vec![0; 8]).unwrap();
+    let result = jit.compile_program("global g = 1\nfn f() -> (r) { r = g }");
+    assert!(matches!(result, Err(JitError::Redefinition { name, .. }) if name == "g"));
+
+    jit.compile_program("global h = 1\nfn f() -> (r) { r = h }")
+        .unwrap();
+    let (r,): (i64,) = unsafe { jit.call("f", ()) }.unwrap();
+    assert_eq!(r, 1);
+}
+
+#[test]
+fn unsigned_operators() {
+    let mut jit = JIT::default();
+    jit.compile("fn g(a, u) -> (r) { r = a/u - 1 }").unwrap();
+    let (r,): (i64,) = unsafe { jit.call("g", (20_i64, 10_i64)) }.unwrap();
+    assert_eq!(r, 1);
+
+    jit.compile(
+        "fn h(a, b) -> (q, r, lt) { q = udiv(a, b); r = urem(a, b); lt = ult(a, b) as i64 }",
+    )
+    .unwrap();
+    let (q, r, lt): (i64, i64, i64) = unsafe { jit.call("h", (-1_i64, 2_i64)) }.unwrap();
+    assert_eq!((q, r, lt), (i64::MAX, 1, 0));
+}
+
+#[test]
+fn reserved_names() {
+    let mut jit = JIT::default();
+    let result = jit.compile("fn __toy_trap(a, b) -> (r) { r = a / b }");
+    assert!(matches!(result, Err(JitError::ReservedName { name, .. }) if name == "__toy_trap"));
+    let result = jit.compile("fn f(a) -> (r) { r = __toy_trap(a) }");
+    assert!(matches!(result, Err(JitError::ReservedName { name, .. }) if name == "__toy_trap"));
+
+    jit.compile("fn f(a, b) -> (r) { r = a / b }").unwrap();
+    let (r,): (i64,) = unsafe { jit.call("f", (7_i64, 2_i64)) }.unwrap();
+    assert_eq!(r, 3);
+}
+
+#[test]
+fn bounds_checks() {
+    let mut jit = JIT::default();
+    jit.set_bounds_checks(true);
+    jit.create_data("numbers", vec![0; 16]).unwrap();
+    jit.compile("fn get(i) -> (r) { r = load64(&numbers + i * 8) }")
+        .unwrap();
+
+    let (r,): (i64,) = unsafe { jit.call("get", (1_i64,)) }.unwrap();
+    assert_eq!(r, 0);
+    let result: Result<(i64,), _> = unsafe { jit.call("get", (2_i64,)) };
+    assert!(matches!(
+        result,
+        Err(JitError::Trap(Trap::OutOfBounds { symbol, offset: 16, width: 8 })) if symbol == "numbers"
+    ));
+
+    jit.compile(r#"fn poke() -> () { store8("hello", 1) }"#)
+        .unwrap();
+    let result: Result<(), _> = unsafe { jit.call("poke", ()) };
+    assert!(matches!(
+        result,
+        Err(JitError::Trap(Trap::ReadOnly { symbol, offset: 0 })) if symbol == "\"hello\""
+    ));
+
+    jit.compile("fn null() -> (r) { r = load64(0 as ptr) }")
+        .unwrap();
+    let result: Result<(i64,), _> = unsafe { jit.call("null", ()) };
+    assert!(matches!(
+        result,
+        Err(JitError::Trap(Trap::InvalidAddress {
+            address: 0,
+            width: 8
+        }))
+    ));
+}
+
+#[test]
+fn for_loop_ending_at_maximum() {
+    let mut jit = JIT::default();
+    jit.compile("fn f() -> (n) { i: i8 = 0; for i in 125..=127 { n = n + 1 } }")
+        .unwrap();
+    let (n,): (i64,) = unsafe { jit.call("f", ()) }.unwrap();
+    assert_eq!(n, 3);
+
+    jit.compile("fn g() -> (n) { i: i8 = 0; for i in 120..127 step 10 { n = n + 1 } }")
+        .unwrap();
+    let (n,): (i64,) = unsafe { jit.call("g", ()) }.unwrap();
+    assert_eq!(n, 1);
+}
+
+#[test]
+fn for_loop_step() {
+    let mut jit = JIT::default();
+    let result = jit.compile("fn f() -> (n) { for i in 0..10 step 0 { n = n + 1 } }");
+    assert!(matches!(result, Err(JitError::InvalidStep { .. })));
+    let result = jit.compile("fn f() -> (n) { for i in 0..10 step -1 { n = n + 1 } }");
+    assert!(matches!(result, Err(JitError::InvalidStep { .. })));
+
+    jit.compile("fn f(s) -> (n) { for i in 0..10 step s { n = n + 1 } }")
+        .unwrap();
+    let (n,): (i64,) = unsafe { jit.call("f", (3_i64,)) }.unwrap();
+    assert_eq!(n, 4);
+    let (n,): (i64,) = unsafe { jit.call("f", (0_i64,)) }.unwrap();
+    assert_eq!(n, 0);
+}
+
+#[test]
+fn render_multibyte_character() {
+    let mut jit = JIT::default();
+    let source = "fn f() -> (r) { r = é }";
+    let rendered = jit.compile(source).unwrap_err().render(source);
+    assert!(rendered.ends_with("1 | fn f() -> (r) { r = é }\n  |                     ^\n"));
+}
+
+#[test]
+fn call_ignores_earlier_trap() {
+    let mut jit = JIT::default();
+    let code = jit.compile("fn f(a) -> (r) { r = 1 / a }").unwrap();
+    let f = unsafe { std::mem::transmute::<*const u8, extern "C" fn(i64) -> i64>(code) };
+    assert_eq!(f(0), 0);
+
+    jit.compile("fn g() -> (r) { r = 7 }").unwrap();
+    let (r,): (i64,) = unsafe { jit.call("g", ()) }.unwrap();
+    assert_eq!(r, 7);
+}
+
+#[test]
+fn nothing_runs_after_trap() {
+    let mut jit = JIT::default();
+    jit.compile_program(
+        "global count = 0
+         fn spin(a) -> (r) { while 1 / a == 0 { count = count + 1 } }
+         fn outer(a) -> (r) { r = spin(a); count = 100 }
+         fn get() -> (r) { r = count }",
+    )
+    .unwrap();
+
+    // The division by zero would produce 0, and keep the loop going.
+    let result: Result<(i64,), _> = unsafe { jit.call("outer", (0_i64,)) };
+    assert!(matches!(result, Err(JitError::Trap(Trap::DivisionByZero))));
+    let (count,): (i64,) = unsafe { jit.call("get", ()) }.unwrap();
+    assert_eq!(count, 0);
+
+    jit.set_bounds_checks(true);
+    jit.compile(r#"fn poke() -> () { store8("hello", 1); count = 1 }"#)
+        .unwrap();
+    let result: Result<(), _> = unsafe { jit.call("poke", ()) };
+    assert!(matches!(result, Err(JitError::Trap(Trap::ReadOnly { .. }))));
+    let (count,): (i64,) = unsafe { jit.call("get", ()) }.unwrap();
+    assert_eq!(count, 0);
+}
+
+#[test]
+fn nested_array() {
+    let mut jit = JIT::default();
+    let result = jit.compile("fn f(c) -> (r) { if c { let b[4] }; r = b[0] }");
+    assert!(matches!(result, Err(JitError::NestedArray { .. })));
+
+    jit.compile("fn f(i) -> (r) { let b[4]; b[1] = 5; r = b[i] }")
+        .unwrap();
+    let (r,): (i64,) = unsafe { jit.call("f", (1_i64,)) }.unwrap();
+    assert_eq!(r, 5);
+}
+
+#[test]
+fn full_width_literals() {
+    let mut jit = JIT::default();
+    jit.compile("fn f() -> (a, b, c) { a = 5000000000; b = -9223372036854775808; c = -(1) }")
+        .unwrap();
+    let result: (i64, i64, i64) = unsafe { jit.call("f", ()) }.unwrap();
+    assert_eq!(result, (5_000_000_000, i64::MIN, -1));
+
+    let result = jit.compile("fn g() -> (r) { r = 9223372036854775808 }");
+    assert!(matches!(result, Err(JitError::LiteralOutOfRange { .. })));
+}
+
+#[test]
+fn radix_and_character_literals() {
+    let mut jit = JIT::default();
+    jit.compile(
+        "fn f() -> (a, b: i8, c, d, e, g) { a = 0xf_f; b = 0xff; c = 'a'; d = '\\n'; e = '\\x7f'; g = '\\u{e9}' }",
+    )
+    .unwrap();
+    let result: (i64, i8, i64, i64, i64, i64) = unsafe { jit.call("f", ()) }.unwrap();
+    assert_eq!(result, (255, -1, 97, 10, 127, 0xe9));
+
+    let result = jit.compile("fn g() -> (r: i8) { r = 0x1ff }");
+    assert!(matches!(result, Err(JitError::LiteralOutOfRange { .. })));
+    let result = jit.compile("fn g() -> (r: i8) { r = 'é' }");
+    assert!(matches!(result, Err(JitError::LiteralOutOfRange { .. })));
+}
+
+#[test]
+fn match_patterns() {
+    let mut jit = JIT::default();
+    jit.compile(
+        "fn f(x: i8) -> (r) { r = match x { -128 => 1, -1 | 0x7f => 2, 0 | 1 | 2 | 3 => 3, _ => 4 } }",
+    )
+    .unwrap();
+    for (x, expected) in [
+        (-128_i8, 1),
+        (-1, 2),
+        (127, 2),
+        (0, 3),
+        (3, 3),
+        (4, 4),
+        (-2, 4),
+    ] {
+        let (r,): (i64,) = unsafe { jit.call("f", (x,)) }.unwrap();
+        assert_eq!(r, expected, "x = {x}");
+    }
+
+    jit.compile("fn g(b: bool) -> (r) { match b { true => { r = 1 }, false => { r = 2 } } }")
+        .unwrap();
+    let (r,): (i64,) = unsafe { jit.call("g", (true,)) }.unwrap();
+    assert_eq!(r, 1);
+    let (r,): (i64,) = unsafe { jit.call("g", (false,)) }.unwrap();
+    assert_eq!(r, 2);
+}
+
+#[test]
+fn unreachable_patterns() {
+    let mut jit = JIT::default();
+    let bodies = [
+        "match x { 1 => 2, 0x1 => 3 }",
+        "match x { -1 => 2, 0xff => 3 }",
+        "match x { 1 | 1 => 2 }",
+        "match x { _ => 2, 1 => 3 }",
+        "match x > 0 { true => 2, true => 3 }",
+    ];
+    for body in bodies {
+        let result = jit.compile(&format!("fn f(x: i8) -> (r) {{ r = {body} }}"));
+        assert!(
+            matches!(result, Err(JitError::UnreachablePattern { .. })),
+            "`{body}` compiled"
+        );
+    }
+}
@@ -0,0 +1,72 @@
+use cranelift_jit_demo::error::JitError;
+use cranelift_jit_demo::frontend::parser;
+use cranelift_jit_demo::jit::JIT;
+use cranelift_jit_demo::layout::{StructLayout, layout_struct};
+
+fn lay_out(source: &str, pointer_bytes: u32) -> Result<StructLayout, JitError> {
+    let program = parser::program(source)?;
+    layout_struct(&program.structs[0], pointer_bytes)
+}
+
+fn offsets(layout: &StructLayout) -> Vec<u32> {
+    layout.fields.iter().map(|field| field.offset).collect()
+}
+
+#[test]
+fn padding_and_alignment() {
+    let layout = lay_out("struct S { a: i8, b: i32, c: i16, d: i64, e: bool }", 8).unwrap();
+    assert_eq!(offsets(&layout), [0, 4, 8, 16, 24]);
+    assert_eq!((layout.size, layout.align), (32, 8));
+    assert_eq!(layout.field("d").unwrap().offset, 16);
+    assert!(layout.field("f").is_none());
+
+    // The size is rounded up to the alignment of the largest field only.
+    let layout = lay_out("struct S { a: i16, b: i8 }", 8).unwrap();
+    assert_eq!(offsets(&layout), [0, 2]);
+    assert_eq!((layout.size, layout.align), (4, 2));
+
+    let layout = lay_out("struct S {}", 8).unwrap();
+    assert_eq!((layout.size, layout.align), (0, 1));
+}
+
+#[test]
+fn pointer_fields() {
+    let layout = lay_out("struct S { a: i8, p: ptr, q: &S }", 4).unwrap();
+    assert_eq!(offsets(&layout), [0, 4, 8]);
+    assert_eq!((layout.size, layout.align), (12, 4));
+
+    let layout = lay_out("struct S { a: i8, p: ptr, q: &S }", 8).unwrap();
+    assert_eq!(offsets(&layout), [0, 8, 16]);
+    assert_eq!((layout.size, layout.align), (24, 8));
+}
+
+#[test]
+fn duplicate_field() {
+    let result = lay_out("struct S { a: i8, a: i16 }", 8);
+    assert!(matches!(result, Err(JitError::Redefinition { name, .. }) if name == "a"));
+}
+
+#[test]
+fn field_access_uses_layout() {
+    let mut jit = JIT::default();
+    jit.compile_program(
+        "struct S { a: i8, b: i32, c: i64 }
+         fn get(s: &S) -> (r) { r = s.a as i64 + s.b as i64 + s.c }
+         fn set(s: &S) -> () { s.b = 20 }",
+    )
+    .unwrap();
+    let data = jit
+        .create_struct_data("s", "S { a: 1, b: 2, c: 300 }")
+        .unwrap();
+    assert_eq!(data.len(), 16);
+    assert_eq!(data[0], 1);
+    assert_eq!(data[4..8], 2_i32.to_ne_bytes());
+    assert_eq!(data[8..16], 300_i64.to_ne_bytes());
+
+    let s = data.as_ptr();
+    let (r,): (i64,) = unsafe { jit.call("get", (s,)) }.unwrap();
+    assert_eq!(r, 303);
+    unsafe { jit.call::<_, ()>("set", (s,)) }.unwrap();
+    let (r,): (i64,) = unsafe { jit.call("get", (s,)) }.unwrap();
+    assert_eq!(r, 321);
+}
@@ -0,0 +1,184 @@
+use cranelift_jit_demo::error::JitError;
+use cranelift_jit_demo::frontend::{Ty, parser};
+use cranelift_jit_demo::typeck::{FunctionType, check_function, parse_literal};
+use std::collections::{HashMap, HashSet};
+
+/// Type check a single function which may call `f(i32) -> (f64)`, returning
+/// its variables.
+fn check(source: &str) -> Result<Vec<(String, Ty)>, JitError> {
+    let mut function = parser::function(source)?;
+    let functions = HashMap::from([(
+        "f".to_owned(),
+        FunctionType {
+            params: vec![Ty::I32],
+            returns: vec![Ty::F64],
+        },
+    )]);
+    let mut imports = Vec::new();
+    check_function(
+        &mut function,
+        &functions,
+        &mut imports,
+        &HashMap::new(),
+        &HashMap::new(),
+        &HashSet::new(),
+        8,
+    )
+}
+
+fn variable(variables: &[(String, Ty)], name: &str) -> Ty {
+    variables.iter().find(|(n, _)| n == name).unwrap().1
+}
+
+#[test]
+fn inference() {
+    let variables =
+        check("fn g(a: i16) -> (r) { x = a; y = 1.5; z = f(3); w = a < 2; v = 7; r = 0 }").unwrap();
+    assert_eq!(variable(&variables, "a"), Ty::I16);
+    assert_eq!(variable(&variables, "r"), Ty::I64);
+    assert_eq!(variable(&variables, "x"), Ty::I16);
+    assert_eq!(variable(&variables, "y"), Ty::F64);
+    assert_eq!(variable(&variables, "z"), Ty::F64);
+    assert_eq!(variable(&variables, "w"), Ty::Bool);
+    assert_eq!(variable(&variables, "v"), Ty::I64);
+
+    // An annotation on any assignment gives the variable its type, even if
+    // it comes after the first use.
+    let variables = check("fn g() -> (r) { x = 1; x: i8 = 2; r = x as i64 }").unwrap();
+    assert_eq!(variable(&variables, "x"), Ty::I8);
+}
+
+#[test]
+fn literals_take_type_from_context() {
+    check("fn g(a: i8) -> (r: f32) { b = a + 100; r = 2.5 }").unwrap();
+
+    let result = check("fn g(a: i8) -> (r) { b = a + 200; r = 0 }");
+    assert!(matches!(result, Err(JitError::LiteralOutOfRange { literal, .. }) if literal == "200"));
+    let result = check("fn g() -> (r: f32) { r = 1e39 }");
+    assert!(matches!(result, Err(JitError::LiteralOutOfRange { .. })));
+}
+
+#[test]
+fn mismatches() {
+    let result = check("fn g(a: i32) -> (r) { r = a }");
+    assert!(matches!(
+        result,
+        Err(JitError::TypeMismatch {
+            expected: Ty::I64,
+            found: Ty::I32,
+            ..
+        })
+    ));
+    let result = check("fn g(a: i32, b: i64) -> (r) { r = a + b }");
+    assert!(matches!(result, Err(JitError::TypeMismatch { .. })));
+    let result = check("fn g(a: i64) -> (r) { r = f(a) }");
+    assert!(matches!(
+        result,
+        Err(JitError::TypeMismatch {
+            expected: Ty::I32,
+            found: Ty::I64,
+            ..
+        })
+    ));
+    let result = check("fn g() -> (r) { r = f(1, 2) }");
+    assert!(matches!(
+        result,
+        Err(JitError::ArityMismatch {
+            expected: 1,
+            found: 2,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn operands() {
+    let result = check("fn g(a: f64) -> (r: f64) { r = a & 1.0 }");
+    assert!(matches!(
+        result,
+        Err(JitError::InvalidOperand { ty: Ty::F64, .. })
+    ));
+    let result = check("fn g(a: bool) -> (r: bool) { r = -a }");
+    assert!(matches!(
+        result,
+        Err(JitError::InvalidOperand { ty: Ty::Bool, .. })
+    ));
+    let result = check("fn g(a) -> (r) { r = if a { 1 } else { 2.0 } }");
+    assert!(matches!(result, Err(JitError::NoValue { .. })));
+    let result = check("fn g() -> (r) { r = while 0 < 1 {} }");
+    assert!(matches!(result, Err(JitError::NoValue { .. })));
+}
+
+#[test]
+fn casts() {
+    check("fn g(a: f32, b: bool, p: ptr) -> (r: i8) { r = a as i8 + b as i8 + p as i8 }").unwrap();
+    check("fn g(a: i16) -> (r: f64) { r = a as f32 as f64 }").unwrap();
+
+    let result = check("fn g(a: f64) -> (r: ptr) { r = a as ptr }");
+    assert!(matches!(
+        result,
+        Err(JitError::InvalidCast {
+            from: Ty::F64,
+            to: Ty::Ptr,
+            ..
+        })
+    ));
+    let result = check("fn g(a: bool) -> (r: f32) { r = a as f32 }");
+    assert!(matches!(
+        result,
+        Err(JitError::InvalidCast {
+            from: Ty::Bool,
+            to: Ty::F32,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn undefined_names() {
+    let result = check("fn g() -> (r) { r = x }");
+    assert!(matches!(result, Err(JitError::UndefinedVariable { name, .. }) if name == "x"));
+    let result = check("fn g() -> (r) { r = &nothing }");
+    assert!(matches!(result, Err(JitError::UndefinedVariable { name, .. }) if name == "nothing"));
+}
+
+#[test]
+fn decimal_literals() {
+    assert_eq!(parse_literal("5000000000", false, 64), Some(5_000_000_000));
+    assert_eq!(
+        parse_literal("9223372036854775807", false, 64),
+        Some(i64::MAX)
+    );
+    assert_eq!(parse_literal("9223372036854775808", false, 64), None);
+    assert_eq!(
+        parse_literal("9223372036854775808", true, 64),
+        Some(i64::MIN)
+    );
+    assert_eq!(parse_literal("9223372036854775809", true, 64), None);
+    assert_eq!(parse_literal("99999999999999999999", false, 64), None);
+
+    assert_eq!(parse_literal("127", false, 8), Some(127));
+    assert_eq!(parse_literal("128", false, 8), None);
+    assert_eq!(parse_literal("128", true, 8), Some(-128));
+    assert_eq!(parse_literal("129", true, 8), None);
+}
+
+#[test]
+fn radix_literals() {
+    assert_eq!(parse_literal("0xff", false, 64), Some(255));
+    assert_eq!(parse_literal("0o17", false, 64), Some(15));
+    assert_eq!(parse_literal("0b1010", false, 64), Some(10));
+    assert_eq!(parse_literal("1_000_000", false, 64), Some(1_000_000));
+    assert_eq!(parse_literal("0xdead_beef", false, 64), Some(0xdead_beef));
+
+    // Radix literals are bit patterns, which are sign-extended from the
+    // width of the type.
+    assert_eq!(parse_literal("0xff", false, 8), Some(-1));
+    assert_eq!(parse_literal("0x80", false, 8), Some(-128));
+    assert_eq!(parse_literal("0x7f", false, 8), Some(127));
+    assert_eq!(parse_literal("0x100", false, 8), None);
+    assert_eq!(parse_literal("0b1111_1111_1111_1111", false, 16), Some(-1));
+    assert_eq!(parse_literal("0xffff_ffff_ffff_ffff", false, 64), Some(-1));
+    assert_eq!(parse_literal("0x1_0000_0000_0000_0000", false, 64), None);
+    assert_eq!(parse_literal("0x80", true, 8), Some(-128));
+}